@@ -1,7 +1,10 @@
 use std::{fs::File, io::BufReader, net::Ipv4Addr, path::PathBuf};
 
+use chrono_tz::Tz;
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -14,39 +17,206 @@ pub struct Args {
 }
 
 /// 設定ファイル/設定情報の構造体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct Config {
     /// Network interface name
     pub interface: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub allowed_mac_list: Option<PathBuf>,
+    /// 拒否リストモードで偽装の対象とするMACアドレスの一覧
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denied_mac_list: Option<PathBuf>,
+    /// 許可リストのスケジュールを評価するタイムゾーン (例: "Asia/Tokyo")。省略した場合はUTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Tz>,
+    #[validate(nested)]
     pub arp_proxy: ArpProxyConfig,
+    /// 許可されたMACアドレスの重複使用の検知。省略した場合は既定値で有効にする
+    #[serde(default)]
+    #[validate(nested)]
+    pub duplicate_detection: DuplicateDetectionConfig,
     pub administration: AdministrationConfig,
 }
 
 /// 設定ファイルの一部・プロキシの挙動について定義する
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate, ToSchema)]
+#[validate(schema(function = "validate_arp_proxy"))]
 pub struct ArpProxyConfig {
-    /// 許可されたMACアドレスにも応答する場合 true
+    /// 偽装の対象を決める方式。省略した場合は許可リスト
+    #[serde(default)]
+    pub mode: ProxyMode,
+    /// 許可されたMACアドレスにも応答する場合 true (許可リストモードのみ)
     pub proxy_allowed_macs: bool,
     /// ARP Replyの送信間隔 (s)
+    #[validate(range(min = 1, max = 3600))]
     pub arp_reply_interval: u32,
     /// ARP Replyを送信し続ける時間 (s)
+    #[validate(range(min = 1, max = 86400))]
     pub arp_reply_duration: u32,
 }
 
+/// 偽装の対象を決める方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// 許可リストに含まれないMACアドレスを偽装の対象とする
+    #[default]
+    Allowlist,
+    /// 拒否リストに含まれるMACアドレスのみを偽装の対象とする
+    Denylist,
+}
+
+fn validate_arp_proxy(config: &ArpProxyConfig) -> Result<(), ValidationError> {
+    if config.arp_reply_duration < config.arp_reply_interval {
+        return Err(ValidationError::new(
+            "arp_reply_duration must not be shorter than arp_reply_interval",
+        ));
+    }
+    Ok(())
+}
+
+/// 許可されたMACアドレスを複数の端末が使用していないかを検知する設定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Validate)]
+#[serde(default)]
+pub struct DuplicateDetectionConfig {
+    /// 検知を有効化
+    pub enabled: bool,
+    /// 異なるIPアドレスでの観測を重複とみなす時間 (s)。同じ事象はこの間に1度だけ記録する
+    #[validate(range(min = 1, max = 86400))]
+    pub window: u32,
+    /// `window` の間にIPアドレスがこの回数以上切り替わった場合に頻繁な切り替えとみなす
+    #[validate(range(min = 2))]
+    pub flap_threshold: u32,
+    /// 検知した時点でそのMACアドレスの許可を取り消す
+    pub auto_revoke: bool,
+}
+
+impl Default for DuplicateDetectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window: 60,
+            flap_threshold: 4,
+            auto_revoke: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AdministrationConfig {
     /// 管理用APIを有効化
     pub enable_api: bool,
+    /// 管理用APIをTCPで待ち受ける。Unixドメインソケットのみを使う場合は false
+    #[serde(default = "default_true")]
+    pub enable_tcp: bool,
     /// 管理用API/管理画面にバインドするIPアドレス
     pub listen_address: Ipv4Addr,
     /// 管理用API/管理画面にバインドするポート番号
     pub listen_port: u16,
+    /// 管理用APIの認証設定。省略した場合は認証を行わない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    /// 管理用APIのTLS設定。省略した場合はHTTPで待ち受ける
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    /// クライアント証明書を発行するCAの証明書 (PEM形式)。指定するとTLS接続でクライアント証明書を要求・検証する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+    /// 管理用APIを待ち受けるUnixドメインソケット。TCPと併用できる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<UnixSocketConfig>,
+    /// APIによる設定の変更を設定ファイルに書き戻す
+    #[serde(default)]
+    pub write_back_config: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// 管理用APIを待ち受けるUnixドメインソケットの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnixSocketConfig {
+    /// ソケットファイルのパス
+    pub path: PathBuf,
+    /// ソケットファイルのパーミッション (8進数)。省略した場合は "0600"
+    #[serde(default = "default_socket_mode")]
+    pub mode: String,
+    /// ソケットファイルの所有者 (ユーザ名)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// ソケットファイルの所有グループ (グループ名)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+fn default_socket_mode() -> String {
+    "0600".to_string()
+}
+
+/// 管理用APIのTLS設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// サーバ証明書 (PEM形式、中間証明書を含めてもよい)
+    pub cert_path: PathBuf,
+    /// 秘密鍵 (PEM形式)
+    pub key_path: PathBuf,
+    /// 証明書と秘密鍵が存在しない場合に自己署名証明書を生成する
+    #[serde(default)]
+    pub generate_self_signed: bool,
+}
+
+/// 管理用APIの認証設定
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// `Authorization: Bearer` で受け付けるAPIトークン
+    #[serde(default)]
+    pub tokens: Vec<ApiTokenConfig>,
+    /// 検証済みのクライアント証明書のCommon Nameと権限の対応
+    #[serde(default)]
+    pub client_certificates: Vec<ClientCertificateConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTokenConfig {
+    /// トークンの名前 (ログに記録される)
+    pub name: String,
+    /// トークンのSHA-256ハッシュ (16進数)
+    pub sha256: String,
+    /// トークンに与える権限。省略した場合は admin
+    #[serde(default = "default_role")]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientCertificateConfig {
+    /// クライアント証明書のSubjectのCommon Name (ログに記録される)
+    pub common_name: String,
+    /// 証明書に与える権限。省略した場合は admin
+    #[serde(default = "default_role")]
+    pub role: Role,
+}
+
+/// 管理用APIの権限。viewer < operator < admin の順に強い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// 閲覧のみ
+    Viewer,
+    /// 許可リストなどの変更
+    Operator,
+    /// 設定の変更を含む全ての操作
+    Admin,
+}
+
+fn default_role() -> Role {
+    Role::Admin
 }
 
 pub fn load_config(filepath: &PathBuf) -> Result<Config, anyhow::Error> {
     let file = File::open(filepath)?;
     let reader = BufReader::new(file);
-    let config = serde_json::from_reader(reader)?;
+    let config: Config = serde_json::from_reader(reader)?;
+    config.validate()?;
     Ok(config)
 }
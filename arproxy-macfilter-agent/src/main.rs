@@ -1,16 +1,16 @@
-use std::{
-    env, fs::File, io::BufReader, path::PathBuf, str::FromStr, sync::Arc, thread, time::Duration,
-};
+use std::{env, sync::Arc, thread, time::Duration};
 
 mod config;
 mod networks;
+mod policy;
 mod repositories;
 mod web;
 
 use clap::Parser;
 use config::Args;
-use pnet::util::MacAddr;
-use repositories::{allowed_mac::AllowedMacRepository, config::ConfigRepository};
+use repositories::{
+    allowed_mac::AllowedMacRepository, config::ConfigRepository, denied_mac::DeniedMacRepository,
+};
 use tracing::{debug, error, info, trace, warn};
 
 #[tokio::main]
@@ -32,16 +32,56 @@ async fn main() {
     trace!("{:?}", config);
 
     // repository creation
-    let allowedmac_repo = repositories::allowed_mac::AllowedMacRepositoryForMemory::new();
-    let arplog_repo = repositories::arplog::ArpLogRepositoryForMemory::new();
-    let config_repo = repositories::config::ConfigRepositoryForMemory::new(config);
+    if config.administration.write_back_config {
+        let config_repo =
+            repositories::config::ConfigRepositoryForFile::new(config, args.config_path);
+        start(config_repo).await;
+    } else {
+        let config_repo = repositories::config::ConfigRepositoryForMemory::new(config);
+        start(config_repo).await;
+    }
+}
 
-    if let Some(path) = config_repo.get_config().allowed_mac_list {
-        let allowed_macs = load_allowed_macs(&path);
-        for m in allowed_macs {
-            allowedmac_repo.add(m).expect("[Error] SyncErr");
-        }
+async fn start<C: ConfigRepository>(config_repo: C) {
+    let config = config_repo.get_config();
+    let timezone = config.timezone.unwrap_or(chrono_tz::Tz::UTC);
+    if let Some(path) = config.allowed_mac_list {
+        let allowedmac_repo = repositories::allowed_mac::AllowedMacRepositoryForFile::open(path)
+            .expect("Failed to load allowed mac list file")
+            .with_timezone(timezone);
+        start_with_allowlist(config_repo, allowedmac_repo).await;
+    } else {
+        let allowedmac_repo =
+            repositories::allowed_mac::AllowedMacRepositoryForMemory::new().with_timezone(timezone);
+        start_with_allowlist(config_repo, allowedmac_repo).await;
     }
+}
+
+async fn start_with_allowlist<C, M>(config_repo: C, allowedmac_repo: M)
+where
+    C: ConfigRepository,
+    M: AllowedMacRepository,
+{
+    if let Some(path) = config_repo.get_config().denied_mac_list {
+        let deniedmac_repo = repositories::denied_mac::DeniedMacRepositoryForFile::open(path)
+            .expect("Failed to load denied mac list file");
+        run(config_repo, allowedmac_repo, deniedmac_repo).await;
+    } else {
+        let deniedmac_repo = repositories::denied_mac::DeniedMacRepositoryForMemory::new();
+        run(config_repo, allowedmac_repo, deniedmac_repo).await;
+    }
+}
+
+async fn run<C, M, D>(config_repo: C, allowedmac_repo: M, deniedmac_repo: D)
+where
+    C: ConfigRepository,
+    M: AllowedMacRepository,
+    D: DeniedMacRepository,
+{
+    let arplog_repo = repositories::arplog::ArpLogRepositoryForMemory::new();
+    let host_repo = repositories::host::HostRepositoryForMemory::new();
+    let ndplog_repo = repositories::arplog::NdpLogRepositoryForMemory::new();
+    let event_repo = repositories::event::EventRepositoryForMemory::new();
 
     // network-related
     let iface_name = config_repo.get_config().interface.clone();
@@ -50,16 +90,39 @@ async fn main() {
         .into_iter()
         .find(|iface| iface.name == iface_name)
         .expect("[Error] Interface name not found");
+    let (transmitter, _transmitter_thread) = match networks::transmitter::FrameTransmitter::spawn(
+        &interface,
+        networks::transmitter::TX_QUEUE_CAPACITY,
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("{}. Make sure it is running with root privileges.", e);
+            panic!();
+        }
+    };
+    debug!("Frame transmitter thread spawned");
+    let policy = policy::ConfiguredPolicy::new(
+        config_repo.subscribe(),
+        allowedmac_repo.clone(),
+        deniedmac_repo.clone(),
+    );
     let packet_sender = networks::PacketSender::new(
         config_repo.clone(),
-        allowedmac_repo.clone(),
         arplog_repo.clone(),
+        host_repo.clone(),
+        ndplog_repo.clone(),
+        policy.clone(),
         interface.clone(),
+        transmitter,
     );
     let packet_listener = networks::PacketListener::new(
         config_repo.clone(),
         allowedmac_repo.clone(),
         arplog_repo.clone(),
+        host_repo.clone(),
+        ndplog_repo.clone(),
+        event_repo.clone(),
+        policy,
         interface.clone(),
         packet_sender.clone(),
     );
@@ -67,8 +130,19 @@ async fn main() {
         packet_listener.listen();
     });
     debug!("Packet listener thread spawned");
-    let task1 = packet_sender.send_loop();
+    let sender = packet_sender.clone();
+    let task1 = tokio::spawn(async move { sender.send_loop().await });
     debug!("Packet sender task started");
+    let (healer, heal_requests) = networks::healer::HealRequester::new();
+    let sender = packet_sender.clone();
+    let task2 = tokio::spawn(async move { sender.heal_loop(heal_requests).await });
+    debug!("Healing task started");
+    let task3 = tokio::spawn(repositories::allowed_mac::expire_loop(
+        allowedmac_repo.clone(),
+    ));
+    debug!("Allowed mac expiry task started");
+    let task4 = tokio::spawn(repositories::host::prune_loop(host_repo.clone()));
+    debug!("Host inventory pruning task started");
     thread::sleep(Duration::from_millis(300)); // wait for start packet_sender
     if thread1.is_finished() {
         error!("Something went wrong. Make sure it is running with root privileges.");
@@ -78,53 +152,157 @@ async fn main() {
     // administration
     let admin_config = config_repo.get_config().administration;
     if admin_config.enable_api {
+        let authenticator = admin_config.auth.as_ref().map(|auth| {
+            web::auth::Authenticator::new(auth).expect("Invalid 'administration.auth'")
+        });
         let app = web::route::create_router(
             Arc::new(config_repo.clone()),
             Arc::new(allowedmac_repo.clone()),
             Arc::new(arplog_repo.clone()),
+            Arc::new(host_repo.clone()),
+            Arc::new(event_repo.clone()),
+            Arc::new(deniedmac_repo.clone()),
+            healer,
+            authenticator,
         );
-        let listener =
-            tokio::net::TcpListener::bind((admin_config.listen_address, admin_config.listen_port))
-                .await
-                .expect(
-                    format!(
-                        "Failed to bind TCP listener to address {} and port {}",
-                        admin_config.listen_address, admin_config.listen_port
-                    )
-                    .as_str(),
+        // 全てのリスナーに終了を通知する
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+        let shutdown = |mut rx: tokio::sync::watch::Receiver<()>| async move {
+            let _ = rx.changed().await;
+        };
+        let tcp = async {
+            if admin_config.enable_tcp {
+                serve_tcp(&admin_config, app.clone(), shutdown(shutdown_rx.clone())).await;
+            }
+        };
+        let unix = async {
+            if let Some(socket_config) = admin_config.unix_socket.as_ref() {
+                let listener = web::server::bind_unix(socket_config)
+                    .expect("Invalid 'administration.unix_socket'");
+                info!(
+                    "Administration API listening on unix socket {:?}",
+                    socket_config.path
                 );
+                web::server::serve_unix(listener, app.clone(), shutdown(shutdown_rx.clone()))
+                    .await
+                    .unwrap();
+            }
+        };
+        tokio::join!(tcp, unix, async {
+            shutdown_signal().await;
+            let _ = shutdown_tx.send(());
+        });
+    } else {
+        shutdown_signal().await;
+    }
+
+    // shutdown
+    // パケットリスナのスレッドは受信待ちでブロックしているため待たずに終了する
+    info!("Shutting down");
+    task1.abort();
+    task2.abort();
+    task3.abort();
+    task4.abort();
+    packet_sender.heal_all().await;
+}
+
+/// 管理用APIをTCP (TLSが設定されていればHTTPS) で提供する
+async fn serve_tcp<F>(admin_config: &config::AdministrationConfig, app: axum::Router, signal: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let listener =
+        tokio::net::TcpListener::bind((admin_config.listen_address, admin_config.listen_port))
+            .await
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Failed to bind TCP listener to address {} and port {}",
+                    admin_config.listen_address, admin_config.listen_port
+                )
+            });
+    if let Some(tls_config) = admin_config.tls.as_ref() {
+        let tls_config = web::tls::load_server_config(
+            tls_config,
+            admin_config.listen_address,
+            admin_config.client_ca.as_deref(),
+        )
+        .expect("Invalid 'administration.tls' or 'administration.client_ca'");
+        info!(
+            "Administration API listening on https://{}:{}",
+            admin_config.listen_address, admin_config.listen_port
+        );
+        web::server::serve_tls(listener, tls_config, app, signal)
+            .await
+            .unwrap();
+    } else {
         info!(
             "Administration API listening on http://{}:{}",
             admin_config.listen_address, admin_config.listen_port
         );
-        axum::serve(listener, app).await.unwrap();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(signal)
+            .await
+            .unwrap();
+    }
+}
+
+/// SIGINT または SIGTERM を受け取るまで待つ
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
-    task1.await;
-    thread1.join().unwrap();
 }
 
 /// ConfigとArgsについてのセキュリティチェックを行う
 fn config_security_checkup(config: &config::Config, args: &Args) {
     let admin_config = config.administration.clone();
-    if !admin_config.listen_address.is_loopback() {
-        if !args.insecure {
-            error!("Non-loopback address {:?} is not accepted as 'administration.listen_address'. Administration api DOES NOT REQUIRE LOGIN, consider using ssh port forwarding. If you will ignore the warning and use a non-loopback address, enable the `--insecure` argument.", admin_config.listen_address);
+    let auth_enabled = admin_config.auth.as_ref().is_some_and(|auth| {
+        !auth.tokens.is_empty()
+            || (admin_config.client_ca.is_some() && !auth.client_certificates.is_empty())
+    });
+    if admin_config.auth.is_some() && !auth_enabled {
+        warn!(
+            "No token or client certificate is configured in 'administration.auth'. All API requests will be rejected."
+        );
+    }
+    if admin_config.client_ca.is_some() {
+        if admin_config.tls.is_none() {
+            error!("'administration.client_ca' requires 'administration.tls'.");
+            panic!("Exitting..");
+        }
+        if admin_config.auth.is_none() {
+            warn!("Client certificates are verified but not mapped to roles because 'administration.auth' is not configured.");
+        }
+    }
+    if admin_config.enable_api && !admin_config.enable_tcp && admin_config.unix_socket.is_none() {
+        error!("Either 'administration.enable_tcp' or 'administration.unix_socket' is required to enable the administration api.");
+        panic!("Exitting..");
+    }
+    if admin_config.enable_tcp && !admin_config.listen_address.is_loopback() {
+        if auth_enabled && admin_config.tls.is_some() {
+            debug!(
+                "Administration api is listening on non-loopback address {:?} with TLS.",
+                admin_config.listen_address
+            );
+        } else if auth_enabled {
+            warn!("Administration api is listening on non-loopback address {:?} without TLS. API tokens are sent in cleartext.", admin_config.listen_address);
+        } else if !args.insecure {
+            error!("Non-loopback address {:?} is not accepted as 'administration.listen_address'. Administration api DOES NOT REQUIRE LOGIN unless 'administration.auth' is configured, consider using ssh port forwarding. If you will ignore the warning and use a non-loopback address, enable the `--insecure` argument.", admin_config.listen_address);
             panic!("Exitting..");
         } else {
             warn!("Administration api DOES NOT REQUIRE LOGIN, consider using ssh port forwarding.");
         }
     }
 }
-
-fn load_allowed_macs(path: &PathBuf) -> Vec<MacAddr> {
-    let file = File::open(path)
-        .expect(format!("Failed to open allowed mac list file {:?}", path).as_str());
-    let reader = BufReader::new(file);
-    let macs_str: Vec<String> = serde_json::from_reader::<BufReader<File>, Vec<String>>(reader)
-        .expect(format!("Failed to open allowed mac list file {:?}", path).as_str());
-    let macs = macs_str
-        .iter()
-        .map(|mac_str| MacAddr::from_str(&mac_str).expect("Failed to parse mac address"))
-        .collect::<Vec<MacAddr>>();
-    macs
-}
@@ -1,37 +1,77 @@
-use std::time::{Duration, SystemTime};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    time::{Duration, SystemTime},
+};
 
 use pnet::{
-    datalink::{Channel, DataLinkReceiver, DataLinkSender, NetworkInterface},
+    datalink::{Channel, NetworkInterface},
     packet::{
-        arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket},
+        arp::{ArpHardwareTypes, ArpOperation, ArpOperations, ArpPacket, MutableArpPacket},
         ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+        icmpv6::{ndp::NeighborSolicitPacket, Icmpv6Packet, Icmpv6Types},
+        ip::IpNextHeaderProtocols,
+        ipv6::Ipv6Packet,
         Packet,
     },
     util::MacAddr,
 };
-use tracing::{debug, trace};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error, info, trace, warn};
+
+pub mod detector;
+pub mod healer;
+pub mod ndp;
+pub mod schedule;
+pub mod transmitter;
 
+use crate::config::{Config, DuplicateDetectionConfig, ProxyMode};
+use crate::policy::{Policy, PolicyRequest, Verdict};
 use crate::repositories::{
-    allowed_mac::AllowedMacRepository, arplog::ArpLog, arplog::ArpLogRepository,
+    allowed_mac::{Allowance, AllowedMacRepository, MacPrefix},
+    arplog::{ArpLog, ArpLogRepository, NdpLog},
     config::ConfigRepository,
+    event::{Event, EventKind, EventRepository},
+    host::HostRepository,
+    RepositoryError,
 };
+use detector::DuplicateMacDetector;
+use healer::HealRequest;
+use schedule::SendSchedule;
+use transmitter::FrameTransmitter;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum NetworkError {
+    #[error("Invalid unit size: {0}")]
     UnitSizeError(String),
+    #[error("Failed to open datalink channel: {0}")]
+    ChannelError(String),
+    #[error("Transmit queue is full")]
+    QueueFull,
+    #[error("Transmit queue is closed")]
+    QueueClosed,
 }
 
-pub struct PacketListener<C, M, A>
+pub struct PacketListener<C, M, A, H, N, E, P>
 where
     C: ConfigRepository,
     M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    N: ArpLogRepository<Ipv6Addr>,
+    E: EventRepository,
+    P: Policy,
 {
-    config_repo: C,
+    /// 受信のたびに設定を複製しないよう、最新の設定を参照する
+    config_changes: watch::Receiver<Config>,
     allowedmac_repo: M,
     arplog_repo: A,
+    host_repo: H,
+    ndplog_repo: N,
+    event_repo: E,
+    policy: P,
+    detector: DuplicateMacDetector,
     interface: NetworkInterface,
-    packet_sender: PacketSender<C, M, A>,
+    packet_sender: PacketSender<C, A, H, N, P>,
 }
 
 /*
@@ -47,23 +87,37 @@ where
 }
 */
 
-impl<C, M, A> PacketListener<C, M, A>
+impl<C, M, A, H, N, E, P> PacketListener<C, M, A, H, N, E, P>
 where
     C: ConfigRepository,
     M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    N: ArpLogRepository<Ipv6Addr>,
+    E: EventRepository,
+    P: Policy,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config_repo: C,
         allowedmac_repo: M,
         arplog_repo: A,
+        host_repo: H,
+        ndplog_repo: N,
+        event_repo: E,
+        policy: P,
         interface: NetworkInterface,
-        packet_sender: PacketSender<C, M, A>,
+        packet_sender: PacketSender<C, A, H, N, P>,
     ) -> Self {
         Self {
-            config_repo,
+            config_changes: config_repo.subscribe(),
             allowedmac_repo,
             arplog_repo,
+            host_repo,
+            ndplog_repo,
+            event_repo,
+            policy,
+            detector: DuplicateMacDetector::new(),
             interface,
             packet_sender,
         }
@@ -78,7 +132,11 @@ where
 
         loop {
             match rx.next() {
-                Ok(pkt) => self.handle_frame(pkt).unwrap(),
+                Ok(pkt) => {
+                    if let Err(e) = self.handle_frame(pkt) {
+                        debug!("Malformed frame ignored: {}", e);
+                    }
+                }
                 Err(_e) => panic!("Failed"),
             };
         }
@@ -106,159 +164,798 @@ where
                     "less than minimal ARP frame size".to_string(),
                 ))
             }
+        } else if frame.get_ethertype() == EtherTypes::Ipv6 {
+            if let Some(ipv6_packet) = Ipv6Packet::new(frame.payload()) {
+                self.handle_ipv6(frame.get_source(), &ipv6_packet)
+            } else {
+                Err(NetworkError::UnitSizeError(
+                    "less than minimal IPv6 packet size".to_string(),
+                ))
+            }
         } else {
             Ok(())
         }
     }
 
+    fn handle_ipv6(&self, source: MacAddr, packet: &Ipv6Packet) -> Result<(), NetworkError> {
+        // NDPのメッセージはホップリミット255でなければならない
+        if packet.get_next_header() != IpNextHeaderProtocols::Icmpv6
+            || packet.get_hop_limit() != 255
+        {
+            return Ok(());
+        }
+        match Icmpv6Packet::new(packet.payload()) {
+            Some(icmp) if icmp.get_icmpv6_type() == Icmpv6Types::NeighborSolicit => {
+                if let Some(solicit) = NeighborSolicitPacket::new(packet.payload()) {
+                    self.handle_neighbor_solicit(source, packet.get_source(), &solicit)
+                } else {
+                    Err(NetworkError::UnitSizeError(
+                        "less than minimal Neighbor Solicitation size".to_string(),
+                    ))
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_neighbor_solicit(
+        &self,
+        source: MacAddr,
+        source_ip: Ipv6Addr,
+        frame: &NeighborSolicitPacket,
+    ) -> Result<(), NetworkError> {
+        let ndplog = NdpLog::new(source, source_ip, frame.get_target_addr());
+        // 重複アドレス検出 (送信元が::) には偽装しない
+        if ndplog.sender_ip.is_unspecified()
+            || ndplog.target_ip.is_multicast()
+            || ndplog.sender_ip == ndplog.target_ip
+        {
+            return Ok(());
+        }
+        trace!("NeighborSolicitationReceived: {:?}", ndplog);
+        let request = PolicyRequest::new(
+            ndplog.sender_mac,
+            ndplog.sender_ip.into(),
+            ndplog.target_ip.into(),
+        );
+        let verdict = self.policy.evaluate(&request);
+        match verdict {
+            Verdict::Block => {
+                self.ndplog_repo.put(ndplog.clone()).unwrap_or(());
+                self.packet_sender.send_ndp_spoofing_frame(ndplog, verdict);
+            }
+            Verdict::Monitor => info!(
+                "Monitored Neighbor Solicitation from {} ({}) for {}",
+                ndplog.sender_mac, ndplog.sender_ip, ndplog.target_ip
+            ),
+            Verdict::Allow => {}
+        }
+        Ok(())
+    }
+
+    /// 要求・応答を問わず送信元をインベントリに記録する。ARP Probe (送信元IPが0.0.0.0) は無視する
+    fn record_host(&self, arplog: &ArpLog, verdict: Verdict) {
+        let (mac, ip) = (arplog.sender_mac, arplog.sender_ip);
+        if ip.is_unspecified() || mac == MacAddr::zero() || mac == MacAddr::broadcast() {
+            return;
+        }
+        self.host_repo
+            .put(mac, ip, verdict != Verdict::Block)
+            .unwrap_or(());
+    }
+
+    /// 許可リストに含まれるMACアドレスの不審な使用をイベントとして記録する。許可を取り消した場合は `true` を返す
+    fn inspect_allowed_mac(&self, arplog: &ArpLog, config: &DuplicateDetectionConfig) -> bool {
+        match self.allowedmac_repo.check(
+            &arplog.sender_mac,
+            &arplog.sender_ip.into(),
+            arplog.last_seen,
+        ) {
+            Ok(Allowance::Allowed) => self.detect_duplicate(arplog, config),
+            Ok(Allowance::UnexpectedIp) => {
+                self.record_unexpected_ip(arplog, config);
+                false
+            }
+            Ok(Allowance::NotAllowed) => false,
+            Err(e) => {
+                error!("Failed to check {}: {}", arplog.sender_mac, e);
+                false
+            }
+        }
+    }
+
+    /// `allowed_ips` に含まれないIPアドレスの使用を記録する。既に遮断しているため許可は取り消さない
+    fn record_unexpected_ip(&self, arplog: &ArpLog, config: &DuplicateDetectionConfig) {
+        let window = Duration::from_secs(config.window.into());
+        if !self
+            .detector
+            .report_unexpected(arplog.sender_mac, SystemTime::now(), window)
+        {
+            return;
+        }
+        warn!(
+            "Allowed MAC address {} used unexpected IP address {}",
+            arplog.sender_mac, arplog.sender_ip
+        );
+        let event = Event::new(
+            EventKind::UnexpectedIp,
+            arplog.sender_mac,
+            vec![arplog.sender_ip],
+        );
+        if let Err(e) = self.event_repo.push(event) {
+            error!("Failed to record event: {}", e);
+        }
+    }
+
+    /// 許可されたMACアドレスの重複使用をイベントとして記録する。許可を取り消した場合は `true` を返す
+    fn detect_duplicate(&self, arplog: &ArpLog, config: &DuplicateDetectionConfig) -> bool {
+        let Some((kind, ips)) = self.detector.observe(
+            arplog.sender_mac,
+            arplog.sender_ip,
+            SystemTime::now(),
+            config,
+        ) else {
+            return false;
+        };
+        let mut event = Event::new(kind, arplog.sender_mac, ips);
+        if config.auto_revoke {
+            match self
+                .allowedmac_repo
+                .remove(&MacPrefix::from(arplog.sender_mac))
+            {
+                Ok(()) => {
+                    event.revoked = true;
+                    self.detector.forget(&arplog.sender_mac);
+                }
+                Err(RepositoryError::NotFound) => {
+                    warn!(
+                        "{} is allowed by a prefix rule and was not revoked",
+                        arplog.sender_mac
+                    );
+                }
+                Err(e) => error!("Failed to revoke {}: {}", arplog.sender_mac, e),
+            }
+        }
+        warn!(
+            "Suspicious use of allowed MAC address {} ({:?}): {:?}{}",
+            event.mac,
+            event.kind,
+            event.ips,
+            if event.revoked {
+                ", allowance revoked"
+            } else {
+                ""
+            }
+        );
+        let revoked = event.revoked;
+        if let Err(e) = self.event_repo.push(event) {
+            error!("Failed to record event: {}", e);
+        }
+        revoked
+    }
+
+    /// ARPの送信元を評価し、要求であれば偽装する。ポリシーは1フレームにつき1回だけ評価する
     fn handle_arp(&self, frame: &ArpPacket) -> Result<(), NetworkError> {
-        if frame.get_operation() == ArpOperations::Request {
-            let arplog = ArpLog {
-                sender_mac: frame.get_sender_hw_addr(),
-                sender_ip: frame.get_sender_proto_addr(),
-                target_ip: frame.get_target_proto_addr(),
-                last_seen: SystemTime::now(),
-            };
+        let arplog = ArpLog {
+            sender_mac: frame.get_sender_hw_addr(),
+            sender_ip: frame.get_sender_proto_addr(),
+            target_ip: frame.get_target_proto_addr(),
+            last_seen: SystemTime::now(),
+        };
+        let is_request = frame.get_operation() == ArpOperations::Request;
+        let request = PolicyRequest::arp(arplog.sender_mac, arplog.sender_ip, arplog.target_ip);
+        let mut verdict = self.policy.evaluate(&request);
+        if is_request {
             trace!("ArpRequestReceived: {:?}", arplog);
-            let proxy_config = self.config_repo.get_config().arp_proxy;
-            if proxy_config.proxy_allowed_macs
-                || !self.allowedmac_repo.contains(&arplog.sender_mac).unwrap()
+            let (mode, detection) = {
+                let config = self.config_changes.borrow();
+                (config.arp_proxy.mode, config.duplicate_detection.clone())
+            };
+            if mode == ProxyMode::Allowlist
+                && !arplog.sender_ip.is_unspecified()
+                && self.inspect_allowed_mac(&arplog, &detection)
             {
-                self.arplog_repo.put(arplog.clone());
-                // MUST implement fake arp reply
-                self.packet_sender.send_spoofing_frame(arplog.clone());
+                // 許可を取り消したため評価し直す
+                verdict = self.policy.evaluate(&request);
             }
-            Ok(())
-        } else {
-            Ok(())
         }
+        self.record_host(&arplog, verdict);
+        if !is_request {
+            return Ok(());
+        }
+        if verdict == Verdict::Monitor {
+            info!(
+                "Monitored ARP request from {} ({}) for {}",
+                arplog.sender_mac, arplog.sender_ip, arplog.target_ip
+            );
+        }
+        if verdict == Verdict::Block {
+            self.arplog_repo.put(arplog.clone()).unwrap_or(());
+            // MUST implement fake arp reply
+            self.packet_sender.send_spoofing_frame(arplog, verdict);
+        }
+        Ok(())
     }
 }
 
 #[derive(Clone)]
-pub struct PacketSender<C, M, A>
+pub struct PacketSender<C, A, H, N, P>
 where
     C: ConfigRepository,
-    M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    N: ArpLogRepository<Ipv6Addr>,
+    P: Policy,
 {
     config_repo: C,
-    allowedmac_repo: M,
     arplog_repo: A,
+    host_repo: H,
+    ndplog_repo: N,
+    policy: P,
     interface: NetworkInterface,
-    // _tx: Box<dyn DataLinkSender>,
+    transmitter: FrameTransmitter,
 }
 
-impl<C, M, A> PacketSender<C, M, A>
+impl<C, A, H, N, P> PacketSender<C, A, H, N, P>
 where
     C: ConfigRepository,
-    M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    N: ArpLogRepository<Ipv6Addr>,
+    P: Policy,
 {
     pub fn new(
         config_repo: C,
-        allowedmac_repo: M,
         arplog_repo: A,
+        host_repo: H,
+        ndplog_repo: N,
+        policy: P,
         interface: NetworkInterface,
+        transmitter: FrameTransmitter,
     ) -> Self {
         Self {
             config_repo,
-            allowedmac_repo,
             arplog_repo,
+            host_repo,
+            ndplog_repo,
+            policy,
             interface,
+            transmitter,
         }
     }
 
+    /// ArpLog/NdpLogに従って偽装フレームを送信し続ける
+    ///
+    /// 送信間隔とArpLogの保持期間は設定の変更に追従する
     pub async fn send_loop(&self) {
-        let arplog_life = Duration::from_secs(
-            self.config_repo
-                .get_config()
-                .arp_proxy
-                .arp_reply_duration
-                .into(),
-        );
-        let interval_secs = self.config_repo.get_config().arp_proxy.arp_reply_interval;
-        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.into()));
+        let mut schedule = SendSchedule::new(self.config_repo.subscribe());
         loop {
             let time = SystemTime::now();
-            let arplogs = self.arplog_repo.getall_autoclear(arplog_life);
+            let arplogs = self.arplog_repo.getall_autoclear(schedule.arplog_life());
             for arplog in arplogs.unwrap() {
-                self.send_spoofing_frame(arplog);
+                let verdict = self.evaluate_arplog(&arplog);
+                for frame in self.prepare_frames(arplog, verdict) {
+                    if let Err(e) = self.transmitter.submit(frame).await {
+                        error!("Sendloop: {}", e);
+                    }
+                }
+            }
+            let ndplogs = self.ndplog_repo.getall_autoclear(schedule.arplog_life());
+            for ndplog in ndplogs.unwrap() {
+                let verdict = self.evaluate_ndplog(&ndplog);
+                for frame in self.prepare_ndp_frames(ndplog, verdict) {
+                    if let Err(e) = self.transmitter.submit(frame).await {
+                        error!("Sendloop: {}", e);
+                    }
+                }
             }
-            interval.tick().await;
+            schedule.tick().await;
             debug!(
-                "Sendloop: {:?} sec/loop",
-                time.elapsed().unwrap().as_secs_f64()
+                "Sendloop: {:?} sec/loop (interval {:?})",
+                time.elapsed().unwrap().as_secs_f64(),
+                schedule.period()
             );
         }
     }
+
+    fn evaluate_arplog(&self, arplog: &ArpLog) -> Verdict {
+        let request = PolicyRequest::arp(arplog.sender_mac, arplog.sender_ip, arplog.target_ip);
+        self.policy.evaluate(&request)
+    }
+
+    fn evaluate_ndplog(&self, ndplog: &NdpLog) -> Verdict {
+        let request = PolicyRequest::new(
+            ndplog.sender_mac,
+            ndplog.sender_ip.into(),
+            ndplog.target_ip.into(),
+        );
+        self.policy.evaluate(&request)
+    }
+
+    /// HealRequesterから受け取った要求に従ってネットワークを修復する
+    pub async fn heal_loop(&self, mut requests: mpsc::UnboundedReceiver<HealRequest>) {
+        while let Some(request) = requests.recv().await {
+            debug!("Heal requested: {:?}", request);
+            let arplogs = self
+                .arplog_repo
+                .getall_without_autoclear()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|arplog| match request {
+                    HealRequest::Sender(address) => arplog.sender_mac == address,
+                    HealRequest::Target(address, target_ip) => {
+                        arplog.sender_mac == address && arplog.target_ip == target_ip
+                    }
+                    // スケジュールの時間外などで依然として偽装の対象であれば修復しない
+                    HealRequest::Reevaluate(prefix) => {
+                        prefix.matches(&arplog.sender_mac)
+                            && self.evaluate_arplog(arplog) != Verdict::Block
+                    }
+                })
+                .collect::<Vec<ArpLog>>();
+            match request {
+                HealRequest::Sender(address) => {
+                    self.arplog_repo.remove(&address).unwrap_or(());
+                    self.ndplog_repo.remove(&address).unwrap_or(());
+                }
+                HealRequest::Target(address, target_ip) => {
+                    self.arplog_repo
+                        .remove_target(&address, &target_ip)
+                        .unwrap_or(());
+                }
+                HealRequest::Reevaluate(prefix) => {
+                    for arplog in arplogs.iter() {
+                        self.arplog_repo
+                            .remove_target(&arplog.sender_mac, &arplog.target_ip)
+                            .unwrap_or(());
+                    }
+                    for ndplog in self
+                        .ndplog_repo
+                        .getall_without_autoclear()
+                        .unwrap_or_default()
+                    {
+                        if prefix.matches(&ndplog.sender_mac)
+                            && self.evaluate_ndplog(&ndplog) != Verdict::Block
+                        {
+                            self.ndplog_repo
+                                .remove_target(&ndplog.sender_mac, &ndplog.target_ip)
+                                .unwrap_or(());
+                        }
+                    }
+                }
+            }
+            self.heal(arplogs).await;
+        }
+    }
+
+    /// 全てのArpLogについて偽装を打ち消し、送信が完了するまで待つ (終了時用)
+    pub async fn heal_all(&self) {
+        let arplogs = self
+            .arplog_repo
+            .getall_without_autoclear()
+            .unwrap_or_default();
+        info!("Healing {} spoofed entries", arplogs.len());
+        self.arplog_repo.clear().unwrap_or(());
+        self.ndplog_repo.clear().unwrap_or(());
+        self.heal(arplogs).await;
+        if let Err(e) = self.transmitter.flush().await {
+            error!("Failed to flush healing frames: {}", e);
+        }
+    }
+
+    async fn heal(&self, arplogs: Vec<ArpLog>) {
+        for arplog in arplogs {
+            for frame in self.construct_healing_frames(&arplog) {
+                if let Err(e) = self.transmitter.submit(frame).await {
+                    error!("Heal: {}", e);
+                }
+            }
+        }
+    }
+
+    /// ARPフレームを組み立てる。Ethernetの送信元は常に自身のMACアドレスとする
+    #[allow(clippy::too_many_arguments)]
+    fn construct_arp_frame(
+        &self,
+        destination: MacAddr,
+        operation: ArpOperation,
+        sender_hw_addr: MacAddr,
+        sender_proto_addr: Ipv4Addr,
+        target_hw_addr: MacAddr,
+        target_proto_addr: Ipv4Addr,
+    ) -> [u8; 42] {
+        let mut ethernet_buffer = [0u8; 42];
+        let mut ethernet_frame =
+            MutableEthernetPacket::new(&mut ethernet_buffer).expect("Packet Creation Failed");
+        ethernet_frame.set_source(self.interface.mac.unwrap());
+        ethernet_frame.set_destination(destination);
+        ethernet_frame.set_ethertype(EtherTypes::Arp);
+
+        let mut arp_buffer = [0u8; 28];
+        let mut arp_frame = MutableArpPacket::new(&mut arp_buffer).expect("Packet Creation Failed");
+        arp_frame.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_frame.set_protocol_type(EtherTypes::Ipv4);
+        arp_frame.set_hw_addr_len(6);
+        arp_frame.set_proto_addr_len(4);
+        arp_frame.set_operation(operation);
+        arp_frame.set_sender_hw_addr(sender_hw_addr);
+        arp_frame.set_sender_proto_addr(sender_proto_addr);
+        arp_frame.set_target_hw_addr(target_hw_addr);
+        arp_frame.set_target_proto_addr(target_proto_addr);
+
+        ethernet_frame.set_payload(arp_frame.packet());
+        ethernet_buffer
+    }
+
     fn construct_proxyarp_frames(&self, arplog: ArpLog) -> ([u8; 42], [u8; 42]) {
         // (smac, sip, tmac, tip, op)
         // frame1: 正規のARPリクエストに偽装したARP応答
         // frame2: Target IP に指定された機器のARPテーブルを書き換えるためのARP要求
         let sender_mac = self.interface.mac.unwrap();
-        let mut ethernet_buffer1 = [0u8; 42];
-        let mut ethernet_buffer2 = [0u8; 42];
-        let mut ethernet_frame1 =
-            MutableEthernetPacket::new(&mut ethernet_buffer1).expect("Packet Creation Failed");
-        let mut ethernet_frame2 =
-            MutableEthernetPacket::new(&mut ethernet_buffer2).expect("Packet Creation Failed");
-        ethernet_frame1.set_source(sender_mac);
-        ethernet_frame1.set_destination(arplog.sender_mac);
-        ethernet_frame2.set_source(sender_mac);
-        ethernet_frame2.set_destination(MacAddr::broadcast());
-
-        ethernet_frame1.set_ethertype(EtherTypes::Arp);
-        ethernet_frame2.set_ethertype(EtherTypes::Arp);
-
-        let mut arp_buffer1 = [0u8; 28];
-        let mut arp_buffer2 = [0u8; 28];
-        let mut arp_frame1 =
-            MutableArpPacket::new(&mut arp_buffer1).expect("Packet Creation Failed");
-        let mut arp_frame2 =
-            MutableArpPacket::new(&mut arp_buffer2).expect("Packet Creation Failed");
-        arp_frame1.set_hardware_type(ArpHardwareTypes::Ethernet);
-        arp_frame2.set_hardware_type(ArpHardwareTypes::Ethernet);
-        arp_frame1.set_protocol_type(EtherTypes::Ipv4);
-        arp_frame2.set_protocol_type(EtherTypes::Ipv4);
-        arp_frame1.set_hw_addr_len(6);
-        arp_frame2.set_hw_addr_len(6);
-        arp_frame1.set_proto_addr_len(4);
-        arp_frame2.set_proto_addr_len(4);
-
-        arp_frame1.set_sender_hw_addr(sender_mac);
-        arp_frame1.set_sender_proto_addr(arplog.target_ip);
-        arp_frame1.set_target_hw_addr(arplog.sender_mac);
-        arp_frame1.set_target_proto_addr(arplog.sender_ip);
-        arp_frame1.set_operation(ArpOperations::Reply);
-
-        arp_frame2.set_sender_hw_addr(sender_mac);
-        arp_frame2.set_sender_proto_addr(arplog.sender_ip);
-        arp_frame2.set_target_hw_addr(MacAddr::zero());
-        arp_frame2.set_target_proto_addr(arplog.target_ip);
-        arp_frame2.set_operation(ArpOperations::Request);
-
-        ethernet_frame1.set_payload(arp_frame1.packet());
-        ethernet_frame2.set_payload(arp_frame2.packet());
-
-        (ethernet_buffer1, ethernet_buffer2)
+        let frame1 = self.construct_arp_frame(
+            arplog.sender_mac,
+            ArpOperations::Reply,
+            sender_mac,
+            arplog.target_ip,
+            arplog.sender_mac,
+            arplog.sender_ip,
+        );
+        let frame2 = self.construct_arp_frame(
+            MacAddr::broadcast(),
+            ArpOperations::Request,
+            sender_mac,
+            arplog.sender_ip,
+            MacAddr::zero(),
+            arplog.target_ip,
+        );
+        (frame1, frame2)
     }
 
-    pub fn send_spoofing_frame(&self, arplog: ArpLog) {
-        if self
-            .allowedmac_repo
-            .contains(&arplog.sender_mac)
-            .unwrap_or(false)
-        {
-            self.arplog_repo.remove(&arplog.sender_mac).unwrap_or(())
+    fn construct_proxyndp_frames(
+        &self,
+        ndplog: NdpLog,
+    ) -> ([u8; ndp::NDP_FRAME_LEN], [u8; ndp::NDP_FRAME_LEN]) {
+        // frame1: 正規のNeighbor Solicitationに偽装したNeighbor Advertisement
+        // frame2: Target IP に指定された機器の近隣キャッシュを書き換えるためのNeighbor Solicitation
+        let sender_mac = self.interface.mac.unwrap();
+        let frame1 = ndp::construct_neighbor_advert(
+            sender_mac,
+            ndplog.sender_mac,
+            ndplog.target_ip,
+            ndplog.sender_ip,
+            ndplog.target_ip,
+            sender_mac,
+        );
+        let frame2 = ndp::construct_neighbor_solicit(
+            sender_mac,
+            ndplog.sender_ip,
+            sender_mac,
+            ndplog.target_ip,
+        );
+        (frame1, frame2)
+    }
+
+    /// 偽装フレームを打ち消すフレームを組み立てる
+    fn construct_healing_frames(&self, arplog: &ArpLog) -> Vec<Vec<u8>> {
+        // frame1: Sender に Target IP の正規のMACアドレスを伝えるARP応答
+        //         Target の正規のMACアドレスを学習していない場合は送信できない
+        // frame2: frame2 (偽装) と同じくブロードキャストで Sender の正規のMACアドレスを伝えるARP要求
+        let mut frames = Vec::new();
+        if let Ok(Some(target_mac)) = self.host_repo.lookup_mac(&arplog.target_ip) {
+            frames.push(
+                self.construct_arp_frame(
+                    arplog.sender_mac,
+                    ArpOperations::Reply,
+                    target_mac,
+                    arplog.target_ip,
+                    arplog.sender_mac,
+                    arplog.sender_ip,
+                )
+                .to_vec(),
+            );
+        } else {
+            debug!(
+                "Genuine MAC address of {} is unknown, skipped healing {}",
+                arplog.target_ip, arplog.sender_mac
+            );
+        }
+        frames.push(
+            self.construct_arp_frame(
+                MacAddr::broadcast(),
+                ArpOperations::Request,
+                arplog.sender_mac,
+                arplog.sender_ip,
+                MacAddr::zero(),
+                arplog.target_ip,
+            )
+            .to_vec(),
+        );
+        frames
+    }
+
+    /// 送信するフレームを用意する。偽装の対象外となった送信元であればArpLogを削除し、修復する
+    fn prepare_frames(&self, arplog: ArpLog, verdict: Verdict) -> Vec<Vec<u8>> {
+        if verdict != Verdict::Block {
+            self.arplog_repo.remove(&arplog.sender_mac).unwrap_or(());
+            return self.construct_healing_frames(&arplog);
         }
-        let (mut tx, _rx) = match pnet::datalink::channel(&self.interface, Default::default()) {
-            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unknown channel type"),
-            Err(e) => panic!("Error happened {}", e),
-        };
         let (raw_frame1, raw_frame2) = self.construct_proxyarp_frames(arplog);
-        tx.send_to(&raw_frame1, Some(self.interface.clone()));
-        tx.send_to(&raw_frame2, Some(self.interface.clone()));
+        vec![raw_frame1.to_vec(), raw_frame2.to_vec()]
+    }
+
+    /// 送信するNDPのフレームを用意する。偽装の対象外となった送信元であればNdpLogを削除する
+    ///
+    /// NDPは近隣到達不能検知によって正規のMACアドレスを再解決するため修復は行わない
+    fn prepare_ndp_frames(&self, ndplog: NdpLog, verdict: Verdict) -> Vec<Vec<u8>> {
+        if verdict != Verdict::Block {
+            self.ndplog_repo.remove(&ndplog.sender_mac).unwrap_or(());
+            return Vec::new();
+        }
+        let (raw_frame1, raw_frame2) = self.construct_proxyndp_frames(ndplog);
+        vec![raw_frame1.to_vec(), raw_frame2.to_vec()]
+    }
+
+    fn try_submit_frames(&self, frames: Vec<Vec<u8>>) {
+        for frame in frames {
+            if let Err(e) = self.transmitter.try_submit(frame) {
+                debug!("Dropping spoofing frame: {}", e);
+                break;
+            }
+        }
+    }
+
+    /// 偽装フレームを送信キューに積む。キューが埋まっている場合は破棄する
+    ///
+    /// `verdict` は受信時の評価結果で、ここでは評価し直さない
+    pub fn send_spoofing_frame(&self, arplog: ArpLog, verdict: Verdict) {
+        self.try_submit_frames(self.prepare_frames(arplog, verdict));
+    }
+
+    /// NDPの偽装フレームを送信キューに積む。キューが埋まっている場合は破棄する
+    pub fn send_ndp_spoofing_frame(&self, ndplog: NdpLog, verdict: Verdict) {
+        self.try_submit_frames(self.prepare_ndp_frames(ndplog, verdict));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, net::Ipv4Addr, str::FromStr};
+
+    use chrono::{Datelike, NaiveTime, Utc};
+    use ipnetwork::Ipv4Network;
+    use pnet::{
+        datalink::{DataLinkSender, NetworkInterface},
+        packet::arp::{ArpOperations, ArpPacket},
+        util::MacAddr,
+    };
+
+    use super::{
+        healer::{HealRequest, HealRequester},
+        transmitter::FrameTransmitter,
+        PacketListener, PacketSender,
+    };
+    use crate::{
+        config::Config,
+        policy::ConfiguredPolicy,
+        repositories::{
+            allowed_mac::{
+                AllowSchedule, AllowedMac, AllowedMacRepository, AllowedMacRepositoryForMemory,
+                MacPrefix,
+            },
+            arplog::{
+                ArpLog, ArpLogRepository, ArpLogRepositoryForMemory, NdpLogRepositoryForMemory,
+            },
+            config::{ConfigRepository, ConfigRepositoryForMemory},
+            denied_mac::DeniedMacRepositoryForMemory,
+            event::{EventKind, EventRepository, EventRepositoryForMemory},
+            host::HostRepositoryForMemory,
+        },
+    };
+
+    /// 送信するフレームを捨てる
+    struct NullSender;
+
+    impl DataLinkSender for NullSender {
+        fn build_and_send(
+            &mut self,
+            _num_packets: usize,
+            _packet_size: usize,
+            _func: &mut dyn FnMut(&mut [u8]),
+        ) -> Option<io::Result<()>> {
+            Some(Ok(()))
+        }
+
+        fn send_to(
+            &mut self,
+            _packet: &[u8],
+            _dst: Option<NetworkInterface>,
+        ) -> Option<io::Result<()>> {
+            Some(Ok(()))
+        }
+    }
+
+    #[test]
+    fn pinned_mac_at_unexpected_ip_is_not_revoked() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "interface": "eth0",
+                "arp_proxy": { "proxy_allowed_macs": false, "arp_reply_interval": 5, "arp_reply_duration": 60 },
+                "administration": { "enable_api": false, "listen_address": "127.0.0.1", "listen_port": 3000 },
+                "duplicate_detection": { "auto_revoke": true }
+            }"#,
+        )
+        .unwrap();
+        let config_repo = ConfigRepositoryForMemory::new(config);
+        let allowedmac_repo = AllowedMacRepositoryForMemory::new();
+        let arplog_repo = ArpLogRepositoryForMemory::new();
+        let host_repo = HostRepositoryForMemory::new();
+        let ndplog_repo = NdpLogRepositoryForMemory::new();
+        let event_repo = EventRepositoryForMemory::new();
+        let policy = ConfiguredPolicy::new(
+            config_repo.subscribe(),
+            allowedmac_repo.clone(),
+            DeniedMacRepositoryForMemory::new(),
+        );
+        let interface = NetworkInterface {
+            name: "eth0".to_string(),
+            description: String::new(),
+            index: 0,
+            mac: Some(MacAddr::new(2, 0, 0, 0, 0, 0xfe)),
+            ips: Vec::new(),
+            flags: 0,
+        };
+        let (transmitter, _) = FrameTransmitter::spawn_with_sender(Box::new(NullSender), 64);
+        let packet_sender = PacketSender::new(
+            config_repo.clone(),
+            arplog_repo.clone(),
+            host_repo.clone(),
+            ndplog_repo.clone(),
+            policy.clone(),
+            interface.clone(),
+            transmitter,
+        );
+        let listener = PacketListener::new(
+            config_repo,
+            allowedmac_repo.clone(),
+            arplog_repo.clone(),
+            host_repo,
+            ndplog_repo,
+            event_repo.clone(),
+            policy,
+            interface,
+            packet_sender.clone(),
+        );
+        let gateway = Ipv4Addr::new(192, 168, 0, 1);
+        let request = |mac: MacAddr, ip: Ipv4Addr| {
+            let frame = packet_sender.construct_arp_frame(
+                MacAddr::broadcast(),
+                ArpOperations::Request,
+                mac,
+                ip,
+                MacAddr::zero(),
+                gateway,
+            );
+            listener
+                .handle_arp(&ArpPacket::new(&frame[14..]).unwrap())
+                .unwrap();
+        };
+        let owner = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let right = Ipv4Addr::new(192, 168, 0, 10);
+        let wrong = Ipv4Addr::new(192, 168, 0, 11);
+        allowedmac_repo
+            .add(AllowedMac {
+                allowed_ips: vec![Ipv4Network::from_str("192.168.0.10").unwrap()],
+                ..AllowedMac::new(owner)
+            })
+            .unwrap();
+
+        // 複製した端末は遮断されるが、所有者の許可は取り消さない
+        request(owner, wrong);
+        request(owner, right);
+        assert!(allowedmac_repo.get(&owner.into()).is_ok());
+        let events = event_repo.getall().unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.kind).collect::<Vec<_>>(),
+            vec![EventKind::UnexpectedIp]
+        );
+        let spoofed = arplog_repo.getall_without_autoclear().unwrap();
+        assert_eq!(spoofed.len(), 1);
+        assert_eq!(spoofed[0].sender_ip, wrong);
+
+        // IPアドレスを指定していない場合は重複使用として取り消す
+        let unpinned = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        allowedmac_repo.add(AllowedMac::new(unpinned)).unwrap();
+        request(unpinned, right);
+        request(unpinned, wrong);
+        assert!(allowedmac_repo.get(&unpinned.into()).is_err());
+        let events = event_repo.getall().unwrap();
+        assert_eq!(events.last().unwrap().kind, EventKind::DuplicateMac);
+        assert!(events.last().unwrap().revoked);
+    }
+
+    #[tokio::test]
+    async fn heal_skips_macs_outside_their_schedule() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "interface": "eth0",
+                "arp_proxy": { "proxy_allowed_macs": false, "arp_reply_interval": 5, "arp_reply_duration": 60 },
+                "administration": { "enable_api": false, "listen_address": "127.0.0.1", "listen_port": 3000 }
+            }"#,
+        )
+        .unwrap();
+        let config_repo = ConfigRepositoryForMemory::new(config);
+        let allowedmac_repo = AllowedMacRepositoryForMemory::new();
+        let arplog_repo = ArpLogRepositoryForMemory::new();
+        let policy = ConfiguredPolicy::new(
+            config_repo.subscribe(),
+            allowedmac_repo.clone(),
+            DeniedMacRepositoryForMemory::new(),
+        );
+        let interface = NetworkInterface {
+            name: "eth0".to_string(),
+            description: String::new(),
+            index: 0,
+            mac: Some(MacAddr::new(2, 0, 0, 0, 0, 0xfe)),
+            ips: Vec::new(),
+            flags: 0,
+        };
+        let (transmitter, _) = FrameTransmitter::spawn_with_sender(Box::new(NullSender), 64);
+        let packet_sender = PacketSender::new(
+            config_repo,
+            arplog_repo.clone(),
+            HostRepositoryForMemory::new(),
+            NdpLogRepositoryForMemory::new(),
+            policy,
+            interface,
+            transmitter,
+        );
+        let gateway = Ipv4Addr::new(192, 168, 0, 1);
+        let scheduled = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let allowed = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        arplog_repo
+            .put(ArpLog::new(
+                scheduled,
+                Ipv4Addr::new(192, 168, 0, 10),
+                gateway,
+            ))
+            .unwrap();
+        arplog_repo
+            .put(ArpLog::new(
+                allowed,
+                Ipv4Addr::new(192, 168, 0, 11),
+                gateway,
+            ))
+            .unwrap();
+        // 3日後の曜日のみ許可する
+        let weekday = Utc::now().weekday().succ().succ().succ();
+        allowedmac_repo
+            .add(AllowedMac {
+                schedules: vec![AllowSchedule {
+                    weekdays: vec![weekday],
+                    start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+                }],
+                ..AllowedMac::new(scheduled)
+            })
+            .unwrap();
+        allowedmac_repo.add(AllowedMac::new(allowed)).unwrap();
+
+        let (healer, requests) = HealRequester::new();
+        for mac in [scheduled, allowed] {
+            healer
+                .request(HealRequest::from(MacPrefix::from(mac)))
+                .unwrap();
+        }
+        drop(healer);
+        packet_sender.heal_loop(requests).await;
+
+        // 時間外のMACアドレスは偽装を続ける
+        let remaining: Vec<MacAddr> = arplog_repo
+            .getall_without_autoclear()
+            .unwrap()
+            .iter()
+            .map(|arplog| arplog.sender_mac)
+            .collect();
+        assert_eq!(remaining, vec![scheduled]);
     }
 }
@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use pnet::util::MacAddr;
+
+use crate::{config::DuplicateDetectionConfig, repositories::event::EventKind};
+
+/// MACアドレスごとの観測履歴
+#[derive(Debug)]
+struct Sightings {
+    last_ip: Ipv4Addr,
+    last_seen: SystemTime,
+    /// IPアドレスごとの最終観測時刻
+    ips: HashMap<Ipv4Addr, SystemTime>,
+    /// IPアドレスが切り替わった時刻
+    switches: VecDeque<SystemTime>,
+    /// 事象ごとの最終記録時刻
+    reported: HashMap<EventKind, SystemTime>,
+}
+
+impl Sightings {
+    fn new(ip: Ipv4Addr, now: SystemTime) -> Self {
+        Self {
+            last_ip: ip,
+            last_seen: now,
+            ips: HashMap::new(),
+            switches: VecDeque::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// 前回の記録から `window` 以上経っていれば記録する
+    fn report(&mut self, kind: EventKind, now: SystemTime, window: Duration) -> bool {
+        if self
+            .reported
+            .get(&kind)
+            .is_some_and(|time| within(*time, now, window))
+        {
+            return false;
+        }
+        self.reported.insert(kind, now);
+        true
+    }
+}
+
+fn within(time: SystemTime, now: SystemTime, window: Duration) -> bool {
+    now.duration_since(time).unwrap_or_default() < window
+}
+
+/// 許可されたMACアドレスが短時間に複数のIPアドレスで使われていないかを検知する
+///
+/// MACアドレスを複製した端末が同時に存在すると、同じMACアドレスのARPリクエストが異なる送信元IPアドレスで交互に観測される
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateMacDetector {
+    sightings: Arc<Mutex<HashMap<MacAddr, Sightings>>>,
+    /// `allowed_ips` の違反を最後に記録した時刻
+    unexpected: Arc<Mutex<HashMap<MacAddr, SystemTime>>>,
+}
+
+impl DuplicateMacDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 許可されたMACアドレスのARPリクエストを記録し、記録すべき事象と関係するIPアドレスを返す
+    ///
+    /// `allowed_ips` に含まれないIPアドレスのリクエストは渡さない。同じ事象は `window` の間に1度だけ返す。
+    /// 無効化されている場合は履歴も残さない
+    pub fn observe(
+        &self,
+        mac: MacAddr,
+        ip: Ipv4Addr,
+        now: SystemTime,
+        config: &DuplicateDetectionConfig,
+    ) -> Option<(EventKind, Vec<Ipv4Addr>)> {
+        if !config.enabled {
+            return None;
+        }
+        let window = Duration::from_secs(config.window.into());
+        let mut sightings = self.sightings.lock().ok()?;
+        if !sightings.contains_key(&mac) {
+            // 観測されなくなったMACアドレスの履歴を捨てる
+            sightings.retain(|_, entry| within(entry.last_seen, now, window));
+        }
+        let entry = sightings
+            .entry(mac)
+            .or_insert_with(|| Sightings::new(ip, now));
+        entry.ips.retain(|_, time| within(*time, now, window));
+        entry.switches.retain(|time| within(*time, now, window));
+        if entry.last_ip != ip {
+            entry.switches.push_back(now);
+        }
+        entry.ips.insert(ip, now);
+        entry.last_ip = ip;
+        entry.last_seen = now;
+
+        let kind = if entry.switches.len() >= config.flap_threshold as usize {
+            EventKind::Flapping
+        } else if entry.ips.len() >= 2 {
+            EventKind::DuplicateMac
+        } else {
+            return None;
+        };
+        if !entry.report(kind, now, window) {
+            return None;
+        }
+        let mut ips: Vec<Ipv4Addr> = entry.ips.keys().copied().collect();
+        ips.sort();
+        Some((kind, ips))
+    }
+
+    /// `allowed_ips` に含まれないIPアドレスの使用を記録すべきか。同じMACアドレスについては `window` の間に1度だけ `true` を返す
+    pub fn report_unexpected(&self, mac: MacAddr, now: SystemTime, window: Duration) -> bool {
+        let Ok(mut unexpected) = self.unexpected.lock() else {
+            return false;
+        };
+        if unexpected
+            .get(&mac)
+            .is_some_and(|time| within(*time, now, window))
+        {
+            return false;
+        }
+        unexpected.retain(|_, time| within(*time, now, window));
+        unexpected.insert(mac, now);
+        true
+    }
+
+    /// 許可を取り消したMACアドレスの履歴を捨てる
+    pub fn forget(&self, mac: &MacAddr) {
+        if let Ok(mut sightings) = self.sightings.lock() {
+            sightings.remove(mac);
+        }
+        if let Ok(mut unexpected) = self.unexpected.lock() {
+            unexpected.remove(mac);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::Ipv4Addr,
+        time::{Duration, SystemTime},
+    };
+
+    use pnet::util::MacAddr;
+
+    use super::DuplicateMacDetector;
+    use crate::{config::DuplicateDetectionConfig, repositories::event::EventKind};
+
+    #[test]
+    fn detect_duplicate_and_flapping() {
+        let detector = DuplicateMacDetector::new();
+        let config = DuplicateDetectionConfig::default();
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let ip1 = Ipv4Addr::new(192, 168, 0, 10);
+        let ip2 = Ipv4Addr::new(192, 168, 0, 11);
+        let start = SystemTime::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(detector.observe(mac, ip1, at(0), &config), None);
+        assert_eq!(detector.observe(mac, ip1, at(1), &config), None);
+        assert_eq!(
+            detector.observe(mac, ip2, at(2), &config),
+            Some((EventKind::DuplicateMac, vec![ip1, ip2]))
+        );
+        // 同じ事象はwindowの間に1度だけ
+        assert_eq!(detector.observe(mac, ip1, at(3), &config), None);
+        assert_eq!(detector.observe(mac, ip2, at(4), &config), None);
+        assert_eq!(
+            detector.observe(mac, ip1, at(5), &config),
+            Some((EventKind::Flapping, vec![ip1, ip2]))
+        );
+        // windowを過ぎれば古いIPアドレスは重複とみなさない
+        assert_eq!(detector.observe(mac, ip2, at(120), &config), None);
+        assert_eq!(
+            detector.observe(mac, ip1, at(121), &config),
+            Some((EventKind::DuplicateMac, vec![ip1, ip2]))
+        );
+        // 別のMACアドレスとは区別する
+        let other = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        assert_eq!(detector.observe(other, ip2, at(122), &config), None);
+    }
+
+    #[test]
+    fn disabled_detector_keeps_no_history() {
+        let detector = DuplicateMacDetector::new();
+        let config = DuplicateDetectionConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let now = SystemTime::now();
+        assert_eq!(
+            detector.observe(mac, Ipv4Addr::new(192, 168, 0, 10), now, &config),
+            None
+        );
+        assert_eq!(
+            detector.observe(mac, Ipv4Addr::new(192, 168, 0, 11), now, &config),
+            None
+        );
+        assert!(detector.sightings.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn report_unexpected_ip() {
+        let detector = DuplicateMacDetector::new();
+        let window = Duration::from_secs(60);
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let other = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        let start = SystemTime::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert!(detector.report_unexpected(mac, at(0), window));
+        // 同じMACアドレスはwindowの間に1度だけ
+        assert!(!detector.report_unexpected(mac, at(1), window));
+        assert!(detector.report_unexpected(other, at(1), window));
+        assert!(detector.report_unexpected(mac, at(61), window));
+        // 履歴を捨てると再び記録する
+        detector.forget(&mac);
+        assert!(detector.report_unexpected(mac, at(62), window));
+    }
+}
@@ -0,0 +1,45 @@
+use std::net::Ipv4Addr;
+
+use pnet::util::MacAddr;
+use tokio::sync::mpsc;
+
+use super::NetworkError;
+use crate::repositories::allowed_mac::MacPrefix;
+
+/// ネットワーク修復の要求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealRequest {
+    /// 送信元MACアドレスに関する全てのArpLogを削除し、偽装を打ち消す
+    Sender(MacAddr),
+    /// 送信元MACアドレスと目標IPアドレスの組に一致するArpLogを削除し、偽装を打ち消す
+    Target(MacAddr, Ipv4Addr),
+    /// 送信元MACアドレスがプレフィックスに一致するArpLog・NdpLogのうち、
+    /// ポリシーが偽装の対象としなくなったものだけを削除し、偽装を打ち消す
+    Reevaluate(MacPrefix),
+}
+
+impl From<MacPrefix> for HealRequest {
+    /// 許可リスト・拒否リストの変更で偽装の対象外となった可能性のある送信元として `Reevaluate`
+    fn from(prefix: MacPrefix) -> Self {
+        HealRequest::Reevaluate(prefix)
+    }
+}
+
+/// PacketSenderの修復タスクに修復を要求するためのハンドル
+#[derive(Debug, Clone)]
+pub struct HealRequester {
+    queue: mpsc::UnboundedSender<HealRequest>,
+}
+
+impl HealRequester {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<HealRequest>) {
+        let (queue, rx) = mpsc::unbounded_channel();
+        (Self { queue }, rx)
+    }
+
+    pub fn request(&self, request: HealRequest) -> Result<(), NetworkError> {
+        self.queue
+            .send(request)
+            .map_err(|_| NetworkError::QueueClosed)
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::Ipv6Addr;
+
+use pnet::{
+    packet::{
+        ethernet::{EtherTypes, MutableEthernetPacket},
+        icmpv6::{
+            self,
+            ndp::{
+                MutableNeighborAdvertPacket, MutableNeighborSolicitPacket, NdpOption,
+                NdpOptionType, NdpOptionTypes, NeighborAdvertFlags,
+            },
+            Icmpv6Code, Icmpv6Packet, Icmpv6Types, MutableIcmpv6Packet,
+        },
+        ip::IpNextHeaderProtocols,
+        ipv6::MutableIpv6Packet,
+        Packet,
+    },
+    util::MacAddr,
+};
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV6_HEADER_LEN: usize = 40;
+/// NS/NA本体 (24 octets) + Link-Layer Address Option (8 octets)
+const NDP_MESSAGE_LEN: usize = 32;
+pub const NDP_FRAME_LEN: usize = ETHERNET_HEADER_LEN + IPV6_HEADER_LEN + NDP_MESSAGE_LEN;
+
+/// 要請ノードマルチキャストアドレスとそれに対応するMACアドレスを求める
+pub fn solicited_node_multicast(address: &Ipv6Addr) -> (Ipv6Addr, MacAddr) {
+    let o = address.octets();
+    let ip = Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | o[13] as u16,
+        (o[14] as u16) << 8 | o[15] as u16,
+    );
+    let mac = MacAddr::new(0x33, 0x33, 0xff, o[13], o[14], o[15]);
+    (ip, mac)
+}
+
+fn link_layer_option(option_type: NdpOptionType, mac: MacAddr) -> NdpOption {
+    NdpOption {
+        option_type,
+        length: 1,
+        data: mac.octets().to_vec(),
+    }
+}
+
+/// ICMPv6メッセージをIPv6パケット・Ethernetフレームに格納する。チェックサムもここで計算する
+fn encapsulate(
+    source_mac: MacAddr,
+    destination_mac: MacAddr,
+    source_ip: Ipv6Addr,
+    destination_ip: Ipv6Addr,
+    message: &mut [u8; NDP_MESSAGE_LEN],
+) -> [u8; NDP_FRAME_LEN] {
+    let checksum = icmpv6::checksum(
+        &Icmpv6Packet::new(message).expect("Packet Creation Failed"),
+        &source_ip,
+        &destination_ip,
+    );
+    MutableIcmpv6Packet::new(message)
+        .expect("Packet Creation Failed")
+        .set_checksum(checksum);
+
+    let mut ipv6_buffer = [0u8; IPV6_HEADER_LEN + NDP_MESSAGE_LEN];
+    let mut ipv6_packet = MutableIpv6Packet::new(&mut ipv6_buffer).expect("Packet Creation Failed");
+    ipv6_packet.set_version(6);
+    ipv6_packet.set_payload_length(NDP_MESSAGE_LEN as u16);
+    ipv6_packet.set_next_header(IpNextHeaderProtocols::Icmpv6);
+    // NDPのメッセージはホップリミット255でなければ破棄される
+    ipv6_packet.set_hop_limit(255);
+    ipv6_packet.set_source(source_ip);
+    ipv6_packet.set_destination(destination_ip);
+    ipv6_packet.set_payload(message);
+
+    let mut ethernet_buffer = [0u8; NDP_FRAME_LEN];
+    let mut ethernet_frame =
+        MutableEthernetPacket::new(&mut ethernet_buffer).expect("Packet Creation Failed");
+    ethernet_frame.set_source(source_mac);
+    ethernet_frame.set_destination(destination_mac);
+    ethernet_frame.set_ethertype(EtherTypes::Ipv6);
+    ethernet_frame.set_payload(ipv6_packet.packet());
+    ethernet_buffer
+}
+
+/// Target Link-Layer Address Optionを持つNeighbor Advertisementを組み立てる
+pub fn construct_neighbor_advert(
+    source_mac: MacAddr,
+    destination_mac: MacAddr,
+    source_ip: Ipv6Addr,
+    destination_ip: Ipv6Addr,
+    target_ip: Ipv6Addr,
+    target_mac: MacAddr,
+) -> [u8; NDP_FRAME_LEN] {
+    let mut message = [0u8; NDP_MESSAGE_LEN];
+    let mut advert =
+        MutableNeighborAdvertPacket::new(&mut message).expect("Packet Creation Failed");
+    advert.set_icmpv6_type(Icmpv6Types::NeighborAdvert);
+    advert.set_icmpv6_code(Icmpv6Code(0));
+    advert.set_flags(NeighborAdvertFlags::Solicited | NeighborAdvertFlags::Override);
+    advert.set_target_addr(target_ip);
+    advert.set_options(&[link_layer_option(NdpOptionTypes::TargetLLAddr, target_mac)]);
+    encapsulate(
+        source_mac,
+        destination_mac,
+        source_ip,
+        destination_ip,
+        &mut message,
+    )
+}
+
+/// Source Link-Layer Address Optionを持つNeighbor Solicitationを組み立てる
+///
+/// 宛先は目標アドレスの要請ノードマルチキャストアドレスとする
+pub fn construct_neighbor_solicit(
+    source_mac: MacAddr,
+    source_ip: Ipv6Addr,
+    source_link_layer: MacAddr,
+    target_ip: Ipv6Addr,
+) -> [u8; NDP_FRAME_LEN] {
+    let (destination_ip, destination_mac) = solicited_node_multicast(&target_ip);
+    let mut message = [0u8; NDP_MESSAGE_LEN];
+    let mut solicit =
+        MutableNeighborSolicitPacket::new(&mut message).expect("Packet Creation Failed");
+    solicit.set_icmpv6_type(Icmpv6Types::NeighborSolicit);
+    solicit.set_icmpv6_code(Icmpv6Code(0));
+    solicit.set_target_addr(target_ip);
+    solicit.set_options(&[link_layer_option(
+        NdpOptionTypes::SourceLLAddr,
+        source_link_layer,
+    )]);
+    encapsulate(
+        source_mac,
+        destination_mac,
+        source_ip,
+        destination_ip,
+        &mut message,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::Ipv6Addr, str::FromStr};
+
+    use pnet::{
+        packet::{
+            ethernet::{EtherTypes, EthernetPacket},
+            icmpv6::{
+                self,
+                ndp::{NdpOptionTypes, NeighborAdvertPacket, NeighborSolicitPacket},
+                Icmpv6Packet, Icmpv6Types,
+            },
+            ipv6::Ipv6Packet,
+            Packet,
+        },
+        util::MacAddr,
+    };
+
+    use super::{construct_neighbor_advert, construct_neighbor_solicit, solicited_node_multicast};
+
+    #[test]
+    fn solicited_node_multicast_address() {
+        let address = Ipv6Addr::from_str("fe80::21b:63ff:fe94:2a1c").unwrap();
+        let (ip, mac) = solicited_node_multicast(&address);
+        assert_eq!(ip, Ipv6Addr::from_str("ff02::1:ff94:2a1c").unwrap());
+        assert_eq!(mac, MacAddr::new(0x33, 0x33, 0xff, 0x94, 0x2a, 0x1c));
+    }
+
+    #[test]
+    fn neighbor_advert_is_well_formed() {
+        let own_mac = MacAddr::new(2, 0, 0, 0, 0, 0xaa);
+        let victim_mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let victim_ip = Ipv6Addr::from_str("fe80::1").unwrap();
+        let target_ip = Ipv6Addr::from_str("fe80::2").unwrap();
+        let frame = construct_neighbor_advert(
+            own_mac, victim_mac, target_ip, victim_ip, target_ip, own_mac,
+        );
+
+        let ethernet = EthernetPacket::new(&frame).unwrap();
+        assert_eq!(ethernet.get_ethertype(), EtherTypes::Ipv6);
+        assert_eq!(ethernet.get_destination(), victim_mac);
+        let ipv6 = Ipv6Packet::new(ethernet.payload()).unwrap();
+        assert_eq!(ipv6.get_hop_limit(), 255);
+        assert_eq!(ipv6.get_source(), target_ip);
+        assert_eq!(ipv6.get_destination(), victim_ip);
+        let icmp = Icmpv6Packet::new(ipv6.payload()).unwrap();
+        assert_eq!(icmp.get_icmpv6_type(), Icmpv6Types::NeighborAdvert);
+        assert_eq!(
+            icmp.get_checksum(),
+            icmpv6::checksum(&icmp, &target_ip, &victim_ip)
+        );
+        let advert = NeighborAdvertPacket::new(ipv6.payload()).unwrap();
+        assert_eq!(advert.get_target_addr(), target_ip);
+        let options = advert.get_options();
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0].option_type, NdpOptionTypes::TargetLLAddr);
+        assert_eq!(options[0].data, own_mac.octets().to_vec());
+    }
+
+    #[test]
+    fn neighbor_solicit_is_well_formed() {
+        let own_mac = MacAddr::new(2, 0, 0, 0, 0, 0xaa);
+        let victim_ip = Ipv6Addr::from_str("fe80::1").unwrap();
+        let target_ip = Ipv6Addr::from_str("fe80::2").unwrap();
+        let frame = construct_neighbor_solicit(own_mac, victim_ip, own_mac, target_ip);
+
+        let ethernet = EthernetPacket::new(&frame).unwrap();
+        let (multicast_ip, multicast_mac) = solicited_node_multicast(&target_ip);
+        assert_eq!(ethernet.get_destination(), multicast_mac);
+        let ipv6 = Ipv6Packet::new(ethernet.payload()).unwrap();
+        assert_eq!(ipv6.get_destination(), multicast_ip);
+        let icmp = Icmpv6Packet::new(ipv6.payload()).unwrap();
+        assert_eq!(icmp.get_icmpv6_type(), Icmpv6Types::NeighborSolicit);
+        assert_eq!(
+            icmp.get_checksum(),
+            icmpv6::checksum(&icmp, &victim_ip, &multicast_ip)
+        );
+        let solicit = NeighborSolicitPacket::new(ipv6.payload()).unwrap();
+        assert_eq!(solicit.get_target_addr(), target_ip);
+        let options = solicit.get_options();
+        assert_eq!(options[0].option_type, NdpOptionTypes::SourceLLAddr);
+        assert_eq!(options[0].data, own_mac.octets().to_vec());
+    }
+}
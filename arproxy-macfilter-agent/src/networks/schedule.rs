@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::watch,
+    time::{Instant, Interval},
+};
+use tracing::info;
+
+use crate::config::{ArpProxyConfig, Config};
+
+/// 設定からsend_loopの送信間隔とArpLogの保持期間を求める
+fn send_loop_timings(config: &ArpProxyConfig) -> (Duration, Duration) {
+    (
+        Duration::from_secs(config.arp_reply_interval.max(1).into()),
+        Duration::from_secs(config.arp_reply_duration.into()),
+    )
+}
+
+/// send_loopの送信間隔とArpLogの保持期間
+///
+/// 設定の変更を監視し、送信間隔が変わった場合は変更した時点から数え直す
+#[derive(Debug)]
+pub struct SendSchedule {
+    config_changes: watch::Receiver<Config>,
+    period: Duration,
+    arplog_life: Duration,
+    interval: Interval,
+    watching: bool,
+}
+
+impl SendSchedule {
+    pub fn new(mut config_changes: watch::Receiver<Config>) -> Self {
+        let (period, arplog_life) =
+            send_loop_timings(&config_changes.borrow_and_update().arp_proxy);
+        Self {
+            config_changes,
+            period,
+            arplog_life,
+            interval: Self::interval(period),
+            watching: true,
+        }
+    }
+
+    fn interval(period: Duration) -> Interval {
+        tokio::time::interval_at(Instant::now() + period, period)
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn arplog_life(&self) -> Duration {
+        self.arplog_life
+    }
+
+    /// 次の送信時刻まで待つ。待っている間の設定の変更は直ちに反映する
+    pub async fn tick(&mut self) {
+        loop {
+            tokio::select! {
+                _ = self.interval.tick() => return,
+                changed = self.config_changes.changed(), if self.watching => {
+                    if changed.is_err() {
+                        self.watching = false;
+                        continue;
+                    }
+                    let (period, arplog_life) =
+                        send_loop_timings(&self.config_changes.borrow_and_update().arp_proxy);
+                    self.arplog_life = arplog_life;
+                    if period != self.period {
+                        info!("Sendloop: interval changed to {:?}", period);
+                        self.period = period;
+                        self.interval = Self::interval(period);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::SendSchedule;
+    use crate::{
+        config::Config,
+        repositories::config::{ConfigRepository, ConfigRepositoryForMemory},
+    };
+
+    fn create_dummy_config_repo() -> ConfigRepositoryForMemory {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "interface": "eth0",
+                "arp_proxy": { "proxy_allowed_macs": false, "arp_reply_interval": 5, "arp_reply_duration": 60 },
+                "administration": { "enable_api": false, "listen_address": "127.0.0.1", "listen_port": 3000 }
+            }"#,
+        )
+        .unwrap();
+        ConfigRepositoryForMemory::new(config)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn schedule_follows_config_changes() {
+        let config_repo = create_dummy_config_repo();
+        let mut schedule = SendSchedule::new(config_repo.subscribe());
+        assert_eq!(schedule.period(), Duration::from_secs(5));
+        assert_eq!(schedule.arplog_life(), Duration::from_secs(60));
+        let start = Instant::now();
+        schedule.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        // 起動後の変更は次の送信から反映する
+        config_repo
+            .update(|config| {
+                config.arp_proxy.arp_reply_interval = 1;
+                config.arp_proxy.arp_reply_duration = 10;
+            })
+            .unwrap();
+        let start = Instant::now();
+        schedule.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert_eq!(schedule.period(), Duration::from_secs(1));
+        assert_eq!(schedule.arplog_life(), Duration::from_secs(10));
+        schedule.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+        // 待っている間に変更された場合は、変更した時点から数え直す
+        let updater = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            config_repo
+                .update(|config| config.arp_proxy.arp_reply_interval = 3)
+                .unwrap();
+        });
+        let start = Instant::now();
+        schedule.tick().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(3500));
+        assert_eq!(schedule.period(), Duration::from_secs(3));
+        assert_eq!(schedule.arplog_life(), Duration::from_secs(10));
+        updater.await.unwrap();
+    }
+}
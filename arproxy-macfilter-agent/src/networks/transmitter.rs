@@ -0,0 +1,174 @@
+use std::thread::{self, JoinHandle};
+
+use pnet::datalink::{Channel, DataLinkSender, NetworkInterface};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+use tracing::{debug, error};
+
+use super::NetworkError;
+
+/// 送信キューに積める最大フレーム数
+pub const TX_QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+enum TxCommand {
+    Frame(Vec<u8>),
+    /// それ以前に積まれたフレームを全て送信したら通知する
+    Flush(oneshot::Sender<()>),
+}
+
+/// 単一のDataLinkSenderを保持する送信スレッドへフレームを渡すためのハンドル
+///
+/// フレーム毎にチャネルを開くとAF_PACKETソケットが大量に作られるため、
+/// 送信は全てこのハンドル経由で専用スレッドに集約する。
+#[derive(Debug, Clone)]
+pub struct FrameTransmitter {
+    queue: mpsc::Sender<TxCommand>,
+}
+
+impl FrameTransmitter {
+    /// インターフェースのチャネルを一度だけ開き、送信スレッドを起動する
+    pub fn spawn(
+        interface: &NetworkInterface,
+        capacity: usize,
+    ) -> Result<(Self, JoinHandle<()>), NetworkError> {
+        let tx = match pnet::datalink::channel(interface, Default::default()) {
+            Ok(Channel::Ethernet(tx, _rx)) => tx,
+            Ok(_) => {
+                return Err(NetworkError::ChannelError(
+                    "Unknown channel type".to_string(),
+                ))
+            }
+            Err(e) => return Err(NetworkError::ChannelError(e.to_string())),
+        };
+        Ok(Self::spawn_with_sender(tx, capacity))
+    }
+
+    /// 任意のDataLinkSenderで送信スレッドを起動する
+    pub fn spawn_with_sender(
+        mut tx: Box<dyn DataLinkSender>,
+        capacity: usize,
+    ) -> (Self, JoinHandle<()>) {
+        let (queue, mut rx) = mpsc::channel::<TxCommand>(capacity);
+        let handle = thread::spawn(move || {
+            while let Some(command) = rx.blocking_recv() {
+                let frame = match command {
+                    TxCommand::Frame(frame) => frame,
+                    TxCommand::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                match tx.send_to(&frame, None) {
+                    Some(Ok(())) => {}
+                    Some(Err(e)) => error!("Failed to send frame: {}", e),
+                    None => error!("Failed to send frame: insufficient buffer"),
+                }
+            }
+            debug!("Frame transmitter stopped");
+        });
+        (Self { queue }, handle)
+    }
+
+    /// キューに空きができるまで待ってフレームを積む
+    pub async fn submit(&self, frame: Vec<u8>) -> Result<(), NetworkError> {
+        self.queue
+            .send(TxCommand::Frame(frame))
+            .await
+            .map_err(|_| NetworkError::QueueClosed)
+    }
+
+    /// それまでに積まれたフレームが全て送信されるまで待つ
+    pub async fn flush(&self) -> Result<(), NetworkError> {
+        let (done, wait) = oneshot::channel();
+        self.queue
+            .send(TxCommand::Flush(done))
+            .await
+            .map_err(|_| NetworkError::QueueClosed)?;
+        wait.await.map_err(|_| NetworkError::QueueClosed)
+    }
+
+    /// 待たずにフレームを積む。キューが埋まっている場合は破棄してエラーを返す
+    pub fn try_submit(&self, frame: Vec<u8>) -> Result<(), NetworkError> {
+        match self.queue.try_send(TxCommand::Frame(frame)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(NetworkError::QueueFull),
+            Err(TrySendError::Closed(_)) => Err(NetworkError::QueueClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use pnet::datalink::{DataLinkSender, NetworkInterface};
+
+    use super::FrameTransmitter;
+    use crate::networks::NetworkError;
+
+    #[derive(Clone, Default)]
+    struct MockSender {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl DataLinkSender for MockSender {
+        fn build_and_send(
+            &mut self,
+            num_packets: usize,
+            packet_size: usize,
+            func: &mut dyn FnMut(&mut [u8]),
+        ) -> Option<io::Result<()>> {
+            for _ in 0..num_packets {
+                let mut buffer = vec![0u8; packet_size];
+                func(&mut buffer);
+                self.sent.lock().unwrap().push(buffer);
+            }
+            Some(Ok(()))
+        }
+
+        fn send_to(
+            &mut self,
+            packet: &[u8],
+            _dst: Option<NetworkInterface>,
+        ) -> Option<io::Result<()>> {
+            self.sent.lock().unwrap().push(packet.to_vec());
+            Some(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_are_sent_in_order_through_one_sender() {
+        let mock = MockSender::default();
+        let (transmitter, handle) = FrameTransmitter::spawn_with_sender(Box::new(mock.clone()), 8);
+        transmitter.submit(vec![1]).await.unwrap();
+        transmitter.try_submit(vec![2]).unwrap();
+        transmitter.submit(vec![3]).await.unwrap();
+        transmitter.flush().await.unwrap();
+        assert_eq!(mock.sent.lock().unwrap().len(), 3);
+        transmitter.submit(vec![4]).await.unwrap();
+        // 全てのハンドルを破棄すると送信スレッドはキューを掃き出して終了する
+        drop(transmitter);
+        handle.join().unwrap();
+        assert_eq!(
+            *mock.sent.lock().unwrap(),
+            vec![vec![1], vec![2], vec![3], vec![4]]
+        );
+    }
+
+    #[test]
+    fn try_submit_fails_when_queue_is_closed() {
+        let (queue, rx) = tokio::sync::mpsc::channel(1);
+        drop(rx);
+        let transmitter = FrameTransmitter { queue };
+        assert!(matches!(
+            transmitter.try_submit(vec![0]),
+            Err(NetworkError::QueueClosed)
+        ));
+    }
+}
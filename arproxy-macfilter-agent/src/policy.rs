@@ -0,0 +1,415 @@
+//! ARPリクエスト・近隣要請の送信元を偽装の対象とするかを決めるポリシー
+//!
+//! `PacketListener` は受信時に、`PacketSender` は偽装を続けるかを判断する際に同じポリシーを評価する。
+//! ポリシーは `and` で組み合わせることができ、関数やクロージャもポリシーとして使用できる。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::SystemTime,
+};
+
+use pnet::util::MacAddr;
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::{
+    config::{Config, ProxyMode},
+    repositories::{
+        allowed_mac::{Allowance, AllowedMacRepository},
+        denied_mac::DeniedMacRepository,
+        RepositoryError,
+    },
+};
+
+/// ポリシーの評価対象
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyRequest {
+    pub sender_mac: MacAddr,
+    /// 送信元IPアドレス。ARPではIPv4、NDPではIPv6
+    pub sender_ip: IpAddr,
+    pub target_ip: IpAddr,
+    /// 受信した時刻。組み込みのポリシーは期限とスケジュールをこの時刻で判断する
+    pub time: SystemTime,
+}
+
+impl PolicyRequest {
+    pub fn new(sender_mac: MacAddr, sender_ip: IpAddr, target_ip: IpAddr) -> Self {
+        Self {
+            sender_mac,
+            sender_ip,
+            target_ip,
+            time: SystemTime::now(),
+        }
+    }
+
+    /// ARPリクエストの送信元と目標から作成する
+    pub fn arp(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        Self::new(sender_mac, sender_ip.into(), target_ip.into())
+    }
+}
+
+/// ポリシーの判定。`Allow` < `Monitor` < `Block` の順に厳しい
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verdict {
+    /// 偽装しない
+    Allow,
+    /// 偽装せず、ログに記録する
+    Monitor,
+    /// 偽装する
+    Block,
+}
+
+pub trait Policy: Clone + std::marker::Send + std::marker::Sync + 'static {
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict;
+
+    /// 両方のポリシーを評価し、より厳しい判定を採用する
+    fn and<P: Policy>(self, other: P) -> Strictest<Self, P>
+    where
+        Self: Sized,
+    {
+        Strictest(self, other)
+    }
+}
+
+/// 関数やクロージャを独自のルールとして使用する
+impl<F> Policy for F
+where
+    F: Fn(&PolicyRequest) -> Verdict + Clone + std::marker::Send + std::marker::Sync + 'static,
+{
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict {
+        self(request)
+    }
+}
+
+/// 2つのポリシーのうち厳しい方の判定を採用する。1つ目が `Block` の場合は2つ目を評価しない
+#[derive(Debug, Clone)]
+pub struct Strictest<A, B>(A, B);
+
+impl<A: Policy, B: Policy> Policy for Strictest<A, B> {
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict {
+        match self.0.evaluate(request) {
+            Verdict::Block => Verdict::Block,
+            verdict => verdict.max(self.1.evaluate(request)),
+        }
+    }
+}
+
+/// レポジトリの参照に失敗した場合は偽装を続ける
+fn or_block(result: Result<Verdict, RepositoryError>) -> Verdict {
+    result.unwrap_or_else(|e| {
+        error!("Failed to evaluate policy: {}", e);
+        Verdict::Block
+    })
+}
+
+/// 許可リストに含まれる送信元のみを許可する
+///
+/// IPv4の送信元は `allowed_ips` とも照合する。期限とスケジュールは `PolicyRequest::time` の時点で判断する
+#[derive(Debug, Clone)]
+pub struct AllowlistPolicy<M: AllowedMacRepository> {
+    allowedmac_repo: M,
+}
+
+impl<M: AllowedMacRepository> AllowlistPolicy<M> {
+    pub fn new(allowedmac_repo: M) -> Self {
+        Self { allowedmac_repo }
+    }
+}
+
+impl<M: AllowedMacRepository> Policy for AllowlistPolicy<M> {
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict {
+        let allowed = self
+            .allowedmac_repo
+            .check(&request.sender_mac, &request.sender_ip, request.time)
+            .map(|allowance| allowance == Allowance::Allowed);
+        or_block(allowed.map(|allowed| {
+            if allowed {
+                Verdict::Allow
+            } else {
+                Verdict::Block
+            }
+        }))
+    }
+}
+
+/// 拒否リストに含まれる送信元のみを偽装の対象とする
+#[derive(Debug, Clone)]
+pub struct DenylistPolicy<D: DeniedMacRepository> {
+    deniedmac_repo: D,
+}
+
+impl<D: DeniedMacRepository> DenylistPolicy<D> {
+    pub fn new(deniedmac_repo: D) -> Self {
+        Self { deniedmac_repo }
+    }
+}
+
+impl<D: DeniedMacRepository> Policy for DenylistPolicy<D> {
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict {
+        or_block(
+            self.deniedmac_repo
+                .contains(&request.sender_mac)
+                .map(|denied| {
+                    if denied {
+                        Verdict::Block
+                    } else {
+                        Verdict::Allow
+                    }
+                }),
+        )
+    }
+}
+
+/// 設定の `proxy_allowed_macs` が有効な場合は全ての送信元を偽装の対象とする
+#[derive(Debug, Clone)]
+pub struct ProxyAllowedMacsPolicy {
+    config_changes: watch::Receiver<Config>,
+}
+
+impl ProxyAllowedMacsPolicy {
+    pub fn new(config_changes: watch::Receiver<Config>) -> Self {
+        Self { config_changes }
+    }
+}
+
+impl Policy for ProxyAllowedMacsPolicy {
+    fn evaluate(&self, _request: &PolicyRequest) -> Verdict {
+        if self.config_changes.borrow().arp_proxy.proxy_allowed_macs {
+            Verdict::Block
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// 設定の `arp_proxy.mode` と `proxy_allowed_macs` に従うポリシー
+///
+/// 許可リストモードでは `ProxyAllowedMacsPolicy` と `AllowlistPolicy` を組み合わせて評価する。
+/// 評価のたびに設定を複製しないよう、`ConfigRepository::subscribe` で受け取った最新の設定を参照する
+#[derive(Debug, Clone)]
+pub struct ConfiguredPolicy<M, D>
+where
+    M: AllowedMacRepository,
+    D: DeniedMacRepository,
+{
+    config_changes: watch::Receiver<Config>,
+    allowlist: Strictest<ProxyAllowedMacsPolicy, AllowlistPolicy<M>>,
+    denylist: DenylistPolicy<D>,
+}
+
+impl<M, D> ConfiguredPolicy<M, D>
+where
+    M: AllowedMacRepository,
+    D: DeniedMacRepository,
+{
+    pub fn new(
+        config_changes: watch::Receiver<Config>,
+        allowedmac_repo: M,
+        deniedmac_repo: D,
+    ) -> Self {
+        Self {
+            allowlist: ProxyAllowedMacsPolicy::new(config_changes.clone())
+                .and(AllowlistPolicy::new(allowedmac_repo)),
+            config_changes,
+            denylist: DenylistPolicy::new(deniedmac_repo),
+        }
+    }
+}
+
+impl<M, D> Policy for ConfiguredPolicy<M, D>
+where
+    M: AllowedMacRepository,
+    D: DeniedMacRepository,
+{
+    fn evaluate(&self, request: &PolicyRequest) -> Verdict {
+        // レポジトリを参照する間は設定の変更を妨げないよう、借用はすぐに手放す
+        let mode = self.config_changes.borrow().arp_proxy.mode;
+        match mode {
+            ProxyMode::Allowlist => self.allowlist.evaluate(request),
+            ProxyMode::Denylist => self.denylist.evaluate(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use chrono::{DateTime, NaiveTime};
+    use ipnetwork::Ipv4Network;
+    use pnet::util::MacAddr;
+
+    use super::{AllowlistPolicy, ConfiguredPolicy, Policy, PolicyRequest, Verdict};
+    use crate::{
+        config::{Config, ProxyMode},
+        repositories::{
+            allowed_mac::{
+                AllowSchedule, AllowedMac, AllowedMacRepository, AllowedMacRepositoryForMemory,
+            },
+            config::{ConfigRepository, ConfigRepositoryForMemory},
+            denied_mac::{DeniedMac, DeniedMacRepository, DeniedMacRepositoryForMemory},
+        },
+    };
+
+    fn create_dummy_config_repo() -> ConfigRepositoryForMemory {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "interface": "eth0",
+                "arp_proxy": { "proxy_allowed_macs": false, "arp_reply_interval": 5, "arp_reply_duration": 60 },
+                "administration": { "enable_api": false, "listen_address": "127.0.0.1", "listen_port": 3000 }
+            }"#,
+        )
+        .unwrap();
+        ConfigRepositoryForMemory::new(config)
+    }
+
+    fn set_config(repo: &ConfigRepositoryForMemory, mode: ProxyMode, proxy_allowed_macs: bool) {
+        repo.update(|config| {
+            config.arp_proxy.mode = mode;
+            config.arp_proxy.proxy_allowed_macs = proxy_allowed_macs;
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn configured_policy_follows_mode() {
+        let config_repo = create_dummy_config_repo();
+        let allowedmac_repo = AllowedMacRepositoryForMemory::new();
+        let deniedmac_repo = DeniedMacRepositoryForMemory::new();
+        let allowed = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let denied = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        let other = MacAddr::new(2, 0, 0, 0, 0xf, 3);
+        allowedmac_repo
+            .add(AllowedMac {
+                allowed_ips: vec![Ipv4Network::from_str("192.168.0.10").unwrap()],
+                ..AllowedMac::new(allowed)
+            })
+            .unwrap();
+        deniedmac_repo.add(DeniedMac::new(denied)).unwrap();
+        let policy =
+            ConfiguredPolicy::new(config_repo.subscribe(), allowedmac_repo, deniedmac_repo);
+        let target = Ipv4Addr::new(192, 168, 0, 1);
+        let request = |mac, ip| PolicyRequest::arp(mac, ip, target);
+        let ip = Ipv4Addr::new(192, 168, 0, 10);
+
+        // 許可リストモード
+        assert_eq!(policy.evaluate(&request(allowed, ip)), Verdict::Allow);
+        assert_eq!(
+            policy.evaluate(&request(allowed, Ipv4Addr::new(192, 168, 0, 11))),
+            Verdict::Block
+        );
+        assert_eq!(policy.evaluate(&request(other, ip)), Verdict::Block);
+        let ndp = PolicyRequest::new(
+            allowed,
+            IpAddr::V6(Ipv6Addr::from_str("fe80::1").unwrap()),
+            IpAddr::V6(Ipv6Addr::from_str("fe80::2").unwrap()),
+        );
+        assert_eq!(policy.evaluate(&ndp), Verdict::Allow);
+        set_config(&config_repo, ProxyMode::Allowlist, true);
+        assert_eq!(policy.evaluate(&request(allowed, ip)), Verdict::Block);
+
+        // 拒否リストモード
+        set_config(&config_repo, ProxyMode::Denylist, true);
+        assert_eq!(policy.evaluate(&request(denied, ip)), Verdict::Block);
+        assert_eq!(policy.evaluate(&request(other, ip)), Verdict::Allow);
+        assert_eq!(
+            policy.evaluate(&request(allowed, Ipv4Addr::new(192, 168, 0, 11))),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn allowlist_policy_uses_request_time() {
+        let at = |text: &str| SystemTime::from(DateTime::parse_from_rfc3339(text).unwrap());
+        let allowedmac_repo = AllowedMacRepositoryForMemory::new();
+        let expiring = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let scheduled = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        allowedmac_repo
+            .add(AllowedMac {
+                expires_at: Some(at("2024-04-05T11:00:00Z")),
+                ..AllowedMac::new(expiring)
+            })
+            .unwrap();
+        allowedmac_repo
+            .add(AllowedMac {
+                schedules: vec![AllowSchedule {
+                    weekdays: Vec::new(),
+                    start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+                }],
+                ..AllowedMac::new(scheduled)
+            })
+            .unwrap();
+        let policy = AllowlistPolicy::new(allowedmac_repo);
+        let request = |mac, time| PolicyRequest {
+            time,
+            ..PolicyRequest::arp(
+                mac,
+                Ipv4Addr::new(192, 168, 0, 10),
+                Ipv4Addr::new(192, 168, 0, 1),
+            )
+        };
+
+        // 評価する時刻ではなく、要求の時刻で期限とスケジュールを判断する
+        let morning = at("2024-04-05T10:00:00Z");
+        assert_eq!(policy.evaluate(&request(expiring, morning)), Verdict::Allow);
+        assert_eq!(
+            policy.evaluate(&request(scheduled, morning)),
+            Verdict::Allow
+        );
+        let evening = morning + Duration::from_secs(9 * 3600);
+        assert_eq!(policy.evaluate(&request(expiring, evening)), Verdict::Block);
+        assert_eq!(
+            policy.evaluate(&request(scheduled, evening)),
+            Verdict::Block
+        );
+    }
+
+    #[test]
+    fn compose_policies() {
+        let gateway = Ipv4Addr::new(192, 168, 0, 1);
+        let allow_all = |_: &PolicyRequest| Verdict::Allow;
+        // 独自のルール: ゲートウェイ宛ての要求を監視する
+        let watch_gateway = move |request: &PolicyRequest| {
+            if request.target_ip == IpAddr::V4(gateway) {
+                Verdict::Monitor
+            } else {
+                Verdict::Allow
+            }
+        };
+        let block_mac = |request: &PolicyRequest| {
+            if request.sender_mac == MacAddr::new(2, 0, 0, 0, 0xf, 9) {
+                Verdict::Block
+            } else {
+                Verdict::Allow
+            }
+        };
+        let policy = allow_all.and(watch_gateway).and(block_mac);
+        let sender = Ipv4Addr::new(192, 168, 0, 10);
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        assert_eq!(
+            policy.evaluate(&PolicyRequest::arp(
+                mac,
+                sender,
+                Ipv4Addr::new(192, 168, 0, 2)
+            )),
+            Verdict::Allow
+        );
+        assert_eq!(
+            policy.evaluate(&PolicyRequest::arp(mac, sender, gateway)),
+            Verdict::Monitor
+        );
+        assert_eq!(
+            policy.evaluate(&PolicyRequest::arp(
+                MacAddr::new(2, 0, 0, 0, 0xf, 9),
+                sender,
+                gateway
+            )),
+            Verdict::Block
+        );
+    }
+}
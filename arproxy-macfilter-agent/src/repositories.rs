@@ -1,6 +1,17 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
 pub mod allowed_mac;
 pub mod arplog;
 pub mod config;
+pub mod denied_mac;
+pub mod event;
+pub mod host;
 
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum RepositoryError {
@@ -8,4 +19,46 @@ pub enum RepositoryError {
     SyncFailed,
     #[error("Resource not found")]
     NotFound,
+    #[error("Resource already exists")]
+    AlreadyExists,
+    #[error("Failed to persist resource")]
+    PersistFailed,
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
+}
+
+/// 一時ファイルに書き出してからrenameで置き換えることで、JSONファイルを原子的に更新する
+///
+/// 既存ファイルのパーミッションは引き継ぐ
+fn write_json_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "Invalid file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let file = File::create(&tmp_path)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, value)?;
+        writer.write_all(b"\n")?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+    // renameを確定させる (失敗しても書き込み自体は完了している)
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
 }
@@ -1,65 +1,526 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use ipnetwork::Ipv4Network;
 use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{BufReader, ErrorKind},
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
 };
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 
-use super::RepositoryError;
+use super::{write_json_atomic, RepositoryError};
+
+mod prefix;
+
+pub use prefix::MacPrefix;
 
 pub trait AllowedMacRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    fn contains(&self, address: &MacAddr) -> Result<bool, RepositoryError>;
-    fn getall(&self) -> Result<Vec<MacAddr>, RepositoryError>;
-    fn add(&self, address: MacAddr) -> Result<MacAddr, RepositoryError>;
-    fn remove(&self, address: &MacAddr) -> Result<(), RepositoryError>;
+    /// `now` の時点で許可されているか、送信元IPアドレスがエントリに指定されたものであるかを調べる。
+    /// MACアドレスに一致するプレフィックスのエントリも含めて調べる
+    ///
+    /// 期限切れのものやスケジュールの時間外のものは許可しない。`allowed_ips` はIPv4の送信元のみを制限する
+    fn check(
+        &self,
+        address: &MacAddr,
+        ip: &IpAddr,
+        now: SystemTime,
+    ) -> Result<Allowance, RepositoryError>;
+    /// 登録されていない場合は `RepositoryError::NotFound` を返す
+    fn get(&self, address: &MacPrefix) -> Result<AllowedMac, RepositoryError>;
+    fn getall(&self) -> Result<Vec<MacPrefix>, RepositoryError>;
+    fn getall_entries(&self) -> Result<Vec<AllowedMac>, RepositoryError>;
+    /// 既に登録されている場合は `RepositoryError::AlreadyExists` を返す
+    fn add(&self, entry: AllowedMac) -> Result<AllowedMac, RepositoryError>;
+    /// 付帯情報を変更する。登録されていない場合は `RepositoryError::NotFound` を返す
+    fn modify(
+        &self,
+        address: &MacPrefix,
+        patch: AllowedMacPatch,
+    ) -> Result<AllowedMac, RepositoryError>;
+    /// 登録されていない場合は `RepositoryError::NotFound` を返す
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError>;
     fn clear(&self) -> Result<(), RepositoryError>;
+    /// 複数のMACアドレスをまとめて変更する。全て反映されるか、何も反映されないかのどちらかとなる
+    fn bulk(
+        &self,
+        operation: BulkOperation,
+        entries: &[AllowedMac],
+    ) -> Result<BulkResult, RepositoryError>;
+    /// `now` の時点で期限切れのエントリを削除し、削除したMACアドレスを返す
+    fn remove_expired(&self, now: SystemTime) -> Result<Vec<MacPrefix>, RepositoryError>;
+}
+
+/// MACアドレスとIPアドレスの組に対する判定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allowance {
+    Allowed,
+    NotAllowed,
+    /// MACアドレスは許可されているが、IPアドレスが指定されたものと異なる
+    UnexpectedIp,
+}
+
+/// 期限切れのエントリを削除する間隔
+pub const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 許可されたMACアドレスとその付帯情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedMac {
+    /// MACアドレス、またはプレフィックス
+    pub address: MacPrefix,
+    /// 端末の名前など
+    pub label: Option<String>,
+    /// 端末の所有者
+    pub owner: Option<String>,
+    pub note: Option<String>,
+    /// 付帯情報のない形式のファイルから読み込んだ場合はNone
+    pub created_at: Option<SystemTime>,
+    /// 許可した利用者の名前
+    pub created_by: Option<String>,
+    pub updated_at: Option<SystemTime>,
+    /// 有効期限。Noneの場合は無期限
+    pub expires_at: Option<SystemTime>,
+    /// 許可する時間帯。空の場合は常に許可する
+    pub schedules: Vec<AllowSchedule>,
+    /// 使用を認めるIPアドレスまたはサブネット。空の場合は制限しない
+    pub allowed_ips: Vec<Ipv4Network>,
+}
+
+impl AllowedMac {
+    /// 付帯情報のないエントリを現在時刻で作成する
+    pub fn new(address: impl Into<MacPrefix>) -> Self {
+        let now = SystemTime::now();
+        Self {
+            address: address.into(),
+            label: None,
+            owner: None,
+            note: None,
+            created_at: Some(now),
+            created_by: None,
+            updated_at: Some(now),
+            expires_at: None,
+            schedules: Vec::new(),
+            allowed_ips: Vec::new(),
+        }
+    }
+
+    /// `now` の時点で有効期限を過ぎているか
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// 現地時刻 `local` がいずれかのスケジュールの時間帯に含まれるか
+    pub fn is_scheduled(&self, local: NaiveDateTime) -> bool {
+        self.schedules.is_empty()
+            || self
+                .schedules
+                .iter()
+                .any(|schedule| schedule.contains(local))
+    }
+
+    /// 送信元IPアドレスとして使用を認めるか。ARP Probe (0.0.0.0) は常に認める
+    pub fn permits_ip(&self, ip: &Ipv4Addr) -> bool {
+        ip.is_unspecified()
+            || self.allowed_ips.is_empty()
+            || self.allowed_ips.iter().any(|network| network.contains(*ip))
+    }
+}
+
+/// 許可する曜日と時間帯
+///
+/// `end` が `start` 以前の場合は日付をまたぐ時間帯とし、`weekdays` は開始する日の曜日とみなす
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowSchedule {
+    /// 空の場合は毎日
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weekdays: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl AllowSchedule {
+    fn on(&self, weekday: Weekday) -> bool {
+        self.weekdays.is_empty() || self.weekdays.contains(&weekday)
+    }
+
+    pub fn contains(&self, local: NaiveDateTime) -> bool {
+        let (weekday, time) = (local.weekday(), local.time());
+        if self.start < self.end {
+            self.on(weekday) && self.start <= time && time < self.end
+        } else {
+            (self.on(weekday) && self.start <= time) || (self.on(weekday.pred()) && time < self.end)
+        }
+    }
+}
+
+/// AllowedMacの変更。Noneの項目は変更せず、空文字列の項目は削除する
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllowedMacPatch {
+    pub label: Option<String>,
+    pub owner: Option<String>,
+    pub note: Option<String>,
+    /// Some(None)の場合は無期限にする
+    pub expires_at: Option<Option<SystemTime>>,
+    /// 空の場合は常に許可する
+    pub schedules: Option<Vec<AllowSchedule>>,
+    /// 空の場合はIPアドレスを制限しない
+    pub allowed_ips: Option<Vec<Ipv4Network>>,
+}
+
+impl AllowedMacPatch {
+    fn apply(self, entry: &mut AllowedMac) {
+        for (field, value) in [
+            (&mut entry.label, self.label),
+            (&mut entry.owner, self.owner),
+            (&mut entry.note, self.note),
+        ] {
+            if let Some(value) = value {
+                *field = Some(value).filter(|value| !value.is_empty());
+            }
+        }
+        if let Some(expires_at) = self.expires_at {
+            entry.expires_at = expires_at;
+        }
+        if let Some(schedules) = self.schedules {
+            entry.schedules = schedules;
+        }
+        if let Some(allowed_ips) = self.allowed_ips {
+            entry.allowed_ips = allowed_ips;
+        }
+        entry.updated_at = Some(SystemTime::now());
+    }
+}
+
+/// 一括操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkOperation {
+    /// 全て追加する
+    Add,
+    /// 全て削除する
+    Remove,
+    /// 指定したもののみを許可する
+    Replace,
+}
+
+/// 一括操作での各MACアドレスの結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkOutcome {
+    Added,
+    Removed,
+    /// 既に許可されていたため変更しなかった
+    Unchanged,
+    /// 許可されていなかったため削除しなかった
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BulkResult {
+    /// 指定された順のMACアドレスごとの結果
+    pub outcomes: Vec<(MacPrefix, BulkOutcome)>,
+    /// Replaceで指定されなかったために削除されたMACアドレス
+    pub removed: Vec<MacPrefix>,
+}
+
+/// 許可リストの内容
+///
+/// MACアドレスとプレフィックスを同じ表で管理し、登録されているプレフィックス長を数えておく。
+/// containsでは登録されている長さについてのみ表を引くため、エントリ数によらず高々49回の検索で済む
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct AllowedMacStore {
+    entries: HashMap<MacPrefix, AllowedMac>,
+    lengths: BTreeMap<u8, usize>,
+}
+
+impl AllowedMacStore {
+    fn get(&self, address: &MacPrefix) -> Option<&AllowedMac> {
+        self.entries.get(address)
+    }
+
+    fn get_mut(&mut self, address: &MacPrefix) -> Option<&mut AllowedMac> {
+        self.entries.get_mut(address)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &MacPrefix> {
+        self.entries.keys()
+    }
+
+    fn values(&self) -> impl Iterator<Item = &AllowedMac> {
+        self.entries.values()
+    }
+
+    /// MACアドレスに一致するエントリを、長いプレフィックスのものから順に返す
+    fn matching<'a>(&'a self, address: &'a MacAddr) -> impl Iterator<Item = &'a AllowedMac> {
+        self.lengths
+            .keys()
+            .rev()
+            .filter_map(|length| self.entries.get(&MacPrefix::new(*address, *length)))
+    }
+
+    fn insert(&mut self, entry: AllowedMac) {
+        let length = entry.address.length();
+        if self.entries.insert(entry.address, entry).is_none() {
+            *self.lengths.entry(length).or_default() += 1;
+        }
+    }
+
+    fn remove(&mut self, address: &MacPrefix) -> Option<AllowedMac> {
+        let removed = self.entries.remove(address)?;
+        if let Some(count) = self.lengths.get_mut(&address.length()) {
+            *count -= 1;
+            if *count == 0 {
+                self.lengths.remove(&address.length());
+            }
+        }
+        Some(removed)
+    }
+
+    fn retain<F: FnMut(&AllowedMac) -> bool>(&mut self, mut f: F) {
+        let removing: Vec<MacPrefix> = self
+            .entries
+            .values()
+            .filter(|entry| !f(entry))
+            .map(|entry| entry.address)
+            .collect();
+        for address in removing {
+            self.remove(&address);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.lengths.clear();
+    }
+}
+
+impl FromIterator<AllowedMac> for AllowedMacStore {
+    fn from_iter<I: IntoIterator<Item = AllowedMac>>(iter: I) -> Self {
+        let mut store = Self::default();
+        for entry in iter {
+            store.insert(entry);
+        }
+        store
+    }
+}
+
+/// 期限切れのエントリは削除されるまでの間も存在しないものとして扱う
+fn lookup<'a>(store: &'a AllowedMacStore, address: &MacPrefix) -> Option<&'a AllowedMac> {
+    let now = SystemTime::now();
+    store.get(address).filter(|entry| !entry.is_expired(now))
+}
+
+/// MACアドレスに一致し、`now` の時点で期限内かつスケジュールの時間帯であるエントリ
+fn active<'a>(
+    store: &'a AllowedMacStore,
+    address: &'a MacAddr,
+    now: SystemTime,
+    timezone: Tz,
+) -> impl Iterator<Item = &'a AllowedMac> {
+    let local = DateTime::<Utc>::from(now)
+        .with_timezone(&timezone)
+        .naive_local();
+    store
+        .matching(address)
+        .filter(move |entry| !entry.is_expired(now) && entry.is_scheduled(local))
+}
+
+/// 有効なエントリのいずれかがIPアドレスの使用を認めていれば許可する
+fn check(
+    store: &AllowedMacStore,
+    address: &MacAddr,
+    ip: &IpAddr,
+    now: SystemTime,
+    timezone: Tz,
+) -> Allowance {
+    let mut allowance = Allowance::NotAllowed;
+    for entry in active(store, address, now, timezone) {
+        let permitted = match ip {
+            IpAddr::V4(ip) => entry.permits_ip(ip),
+            IpAddr::V6(_) => true,
+        };
+        if permitted {
+            return Allowance::Allowed;
+        }
+        allowance = Allowance::UnexpectedIp;
+    }
+    allowance
+}
+
+fn entries(store: &AllowedMacStore) -> impl Iterator<Item = &AllowedMac> {
+    let now = SystemTime::now();
+    store.values().filter(move |entry| !entry.is_expired(now))
+}
+
+/// 期限切れのエントリは上書きする
+fn insert(store: &mut AllowedMacStore, entry: AllowedMac) -> Result<AllowedMac, RepositoryError> {
+    if lookup(store, &entry.address).is_some() {
+        return Err(RepositoryError::AlreadyExists);
+    }
+    store.insert(entry.clone());
+    Ok(entry)
+}
+
+fn modify(
+    store: &mut AllowedMacStore,
+    address: &MacPrefix,
+    patch: AllowedMacPatch,
+) -> Result<AllowedMac, RepositoryError> {
+    let now = SystemTime::now();
+    let entry = store
+        .get_mut(address)
+        .filter(|entry| !entry.is_expired(now))
+        .ok_or(RepositoryError::NotFound)?;
+    patch.apply(entry);
+    Ok(entry.clone())
+}
+
+/// 期限切れのエントリがあるか。削除のために書き込みロックを取る前に読み込みロックで確認する
+fn has_expired(store: &AllowedMacStore, now: SystemTime) -> bool {
+    store.values().any(|entry| entry.is_expired(now))
+}
+
+fn remove_expired(store: &mut AllowedMacStore, now: SystemTime) -> Vec<MacPrefix> {
+    let mut expired: Vec<MacPrefix> = store
+        .values()
+        .filter(|entry| entry.is_expired(now))
+        .map(|entry| entry.address)
+        .collect();
+    expired.sort();
+    store.retain(|entry| !entry.is_expired(now));
+    expired
+}
+
+fn apply_bulk(
+    store: &mut AllowedMacStore,
+    operation: BulkOperation,
+    entries: &[AllowedMac],
+) -> BulkResult {
+    let mut result = BulkResult::default();
+    if operation == BulkOperation::Replace {
+        let keep: HashSet<MacPrefix> = entries.iter().map(|entry| entry.address).collect();
+        result.removed = store
+            .keys()
+            .filter(|address| !keep.contains(address))
+            .copied()
+            .collect();
+        result.removed.sort();
+        store.retain(|entry| keep.contains(&entry.address));
+    }
+    for entry in entries {
+        let outcome = match operation {
+            BulkOperation::Add | BulkOperation::Replace => {
+                if insert(store, entry.clone()).is_ok() {
+                    BulkOutcome::Added
+                } else {
+                    BulkOutcome::Unchanged
+                }
+            }
+            BulkOperation::Remove => {
+                if store.remove(&entry.address).is_some() {
+                    BulkOutcome::Removed
+                } else {
+                    BulkOutcome::NotFound
+                }
+            }
+        };
+        result.outcomes.push((entry.address, outcome));
+    }
+    result
 }
 
 #[derive(Debug, Clone)]
 pub struct AllowedMacRepositoryForMemory {
-    store: Arc<RwLock<HashSet<MacAddr>>>,
+    store: Arc<RwLock<AllowedMacStore>>,
+    timezone: Tz,
 }
 
 impl AllowedMacRepositoryForMemory {
     pub fn new() -> Self {
         Self {
             store: Arc::default(),
+            timezone: Tz::UTC,
         }
     }
+
+    /// スケジュールを評価するタイムゾーンを設定する
+    pub fn with_timezone(self, timezone: Tz) -> Self {
+        Self { timezone, ..self }
+    }
 }
 
 impl AllowedMacRepository for AllowedMacRepositoryForMemory {
-    fn contains(&self, address: &MacAddr) -> Result<bool, RepositoryError> {
+    fn check(
+        &self,
+        address: &MacAddr,
+        ip: &IpAddr,
+        now: SystemTime,
+    ) -> Result<Allowance, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(check(&store, address, ip, now, self.timezone))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn get(&self, address: &MacPrefix) -> Result<AllowedMac, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            lookup(&store, address)
+                .cloned()
+                .ok_or(RepositoryError::NotFound)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<MacPrefix>, RepositoryError> {
         if let Ok(store) = self.store.read() {
-            Ok(store.contains(&address))
+            Ok(entries(&store).map(|entry| entry.address).collect())
         } else {
             Err(RepositoryError::SyncFailed)
         }
     }
 
-    fn getall(&self) -> Result<Vec<MacAddr>, RepositoryError> {
+    fn getall_entries(&self) -> Result<Vec<AllowedMac>, RepositoryError> {
         if let Ok(store) = self.store.read() {
-            Ok(store.clone().into_iter().collect())
+            Ok(entries(&store).cloned().collect())
         } else {
             Err(RepositoryError::SyncFailed)
         }
     }
 
-    fn add(&self, address: MacAddr) -> Result<MacAddr, RepositoryError> {
-        debug!("MAC address putted to AllowedMacRepository: {:?}", address);
+    fn add(&self, entry: AllowedMac) -> Result<AllowedMac, RepositoryError> {
+        debug!("MAC address putted to AllowedMacRepository: {:?}", entry);
         if let Ok(mut store) = self.store.write() {
-            store.insert(address);
-            Ok(address)
+            insert(&mut store, entry)
         } else {
             error!("Repository Error");
             Err(RepositoryError::SyncFailed)
         }
     }
 
-    fn remove(&self, address: &MacAddr) -> Result<(), RepositoryError> {
+    fn modify(
+        &self,
+        address: &MacPrefix,
+        patch: AllowedMacPatch,
+    ) -> Result<AllowedMac, RepositoryError> {
         if let Ok(mut store) = self.store.write() {
-            store.remove(&address);
-            Ok(())
+            modify(&mut store, address, patch)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError> {
+        if let Ok(mut store) = self.store.write() {
+            store
+                .remove(address)
+                .map(|_| ())
+                .ok_or(RepositoryError::NotFound)
         } else {
             Err(RepositoryError::SyncFailed)
         }
@@ -73,41 +534,894 @@ impl AllowedMacRepository for AllowedMacRepositoryForMemory {
             Err(RepositoryError::SyncFailed)
         }
     }
+
+    fn bulk(
+        &self,
+        operation: BulkOperation,
+        entries: &[AllowedMac],
+    ) -> Result<BulkResult, RepositoryError> {
+        if let Ok(mut store) = self.store.write() {
+            Ok(apply_bulk(&mut store, operation, entries))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn remove_expired(&self, now: SystemTime) -> Result<Vec<MacPrefix>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            if !has_expired(&store, now) {
+                return Ok(Vec::new());
+            }
+        } else {
+            return Err(RepositoryError::SyncFailed);
+        }
+        if let Ok(mut store) = self.store.write() {
+            Ok(remove_expired(&mut store, now))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+/// 許可されたMACアドレスをJSONファイルに永続化するレポジトリ
+///
+/// 変更の度にファイル全体を一時ファイルに書き出してからrenameで置き換えるため、
+/// ファイルが書きかけの状態になることはない。書き込みに失敗した場合は変更を取り消す。
+#[derive(Debug, Clone)]
+pub struct AllowedMacRepositoryForFile {
+    store: Arc<RwLock<AllowedMacStore>>,
+    path: Arc<PathBuf>,
+    timezone: Tz,
+}
+
+impl AllowedMacRepositoryForFile {
+    /// ファイルを読み込んでレポジトリを作成する。ファイルが存在しない場合は空とする
+    pub fn open(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let macs = match File::open(&path) {
+            Ok(file) => read_allowed_macs(file)?.into_iter().collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Allowed mac list file {:?} not found, starting empty", path);
+                AllowedMacStore::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            store: Arc::new(RwLock::new(macs)),
+            path: Arc::new(path),
+            timezone: Tz::UTC,
+        })
+    }
+
+    /// スケジュールを評価するタイムゾーンを設定する
+    pub fn with_timezone(self, timezone: Tz) -> Self {
+        Self { timezone, ..self }
+    }
+
+    fn persist(&self, macs: &AllowedMacStore) -> Result<(), RepositoryError> {
+        write_allowed_macs_atomic(&self.path, macs).map_err(|e| {
+            error!(
+                "Failed to write allowed mac list file {:?}: {}",
+                self.path, e
+            );
+            RepositoryError::PersistFailed
+        })
+    }
+
+    /// 変更後の内容をファイルに書き出し、成功した場合のみメモリ上に反映する
+    ///
+    /// `f` がエラーを返した場合は何も変更しない
+    fn update<F, T>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        F: FnOnce(&mut AllowedMacStore) -> Result<T, RepositoryError>,
+    {
+        if let Ok(mut store) = self.store.write() {
+            let mut next = store.clone();
+            let result = f(&mut next)?;
+            if next != *store {
+                self.persist(&next)?;
+                *store = next;
+            }
+            Ok(result)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+impl AllowedMacRepository for AllowedMacRepositoryForFile {
+    fn check(
+        &self,
+        address: &MacAddr,
+        ip: &IpAddr,
+        now: SystemTime,
+    ) -> Result<Allowance, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(check(&store, address, ip, now, self.timezone))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn get(&self, address: &MacPrefix) -> Result<AllowedMac, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            lookup(&store, address)
+                .cloned()
+                .ok_or(RepositoryError::NotFound)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<MacPrefix>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(entries(&store).map(|entry| entry.address).collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall_entries(&self) -> Result<Vec<AllowedMac>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(entries(&store).cloned().collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn add(&self, entry: AllowedMac) -> Result<AllowedMac, RepositoryError> {
+        debug!("MAC address putted to AllowedMacRepository: {:?}", entry);
+        self.update(|store| insert(store, entry))
+    }
+
+    fn modify(
+        &self,
+        address: &MacPrefix,
+        patch: AllowedMacPatch,
+    ) -> Result<AllowedMac, RepositoryError> {
+        self.update(|store| modify(store, address, patch))
+    }
+
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError> {
+        self.update(|store| {
+            store
+                .remove(address)
+                .map(|_| ())
+                .ok_or(RepositoryError::NotFound)
+        })
+    }
+
+    fn clear(&self) -> Result<(), RepositoryError> {
+        self.update(|store| {
+            store.clear();
+            Ok(())
+        })
+    }
+
+    fn bulk(
+        &self,
+        operation: BulkOperation,
+        entries: &[AllowedMac],
+    ) -> Result<BulkResult, RepositoryError> {
+        self.update(|store| Ok(apply_bulk(store, operation, entries)))
+    }
+
+    fn remove_expired(&self, now: SystemTime) -> Result<Vec<MacPrefix>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            if !has_expired(&store, now) {
+                return Ok(Vec::new());
+            }
+        } else {
+            return Err(RepositoryError::SyncFailed);
+        }
+        self.update(|store| Ok(remove_expired(store, now)))
+    }
+}
+
+/// 期限切れのエントリを定期的に削除する
+///
+/// 期限切れのエントリは削除前から許可されていないものとして扱われるため、
+/// 次のARPリクエストから偽装が再開される
+pub async fn expire_loop<M: AllowedMacRepository>(allowedmac_repo: M) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match allowedmac_repo.remove_expired(SystemTime::now()) {
+            Ok(expired) => {
+                for addr in expired {
+                    info!("Allowance for {} expired", addr);
+                }
+            }
+            Err(e) => error!("Failed to remove expired allowed macs: {}", e),
+        }
+    }
+}
+
+/// ファイル中の1件。以前の形式のMACアドレスの文字列も受け付ける
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum AllowedMacFileEntry {
+    Address(String),
+    Record(AllowedMacRecord),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllowedMacRecord {
+    mac_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedules: Vec<AllowSchedule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_ips: Vec<Ipv4Network>,
+}
+
+impl From<&AllowedMac> for AllowedMacRecord {
+    fn from(entry: &AllowedMac) -> Self {
+        Self {
+            mac_address: entry.address.to_string(),
+            label: entry.label.clone(),
+            owner: entry.owner.clone(),
+            note: entry.note.clone(),
+            created_at: entry.created_at.map(DateTime::from),
+            created_by: entry.created_by.clone(),
+            updated_at: entry.updated_at.map(DateTime::from),
+            expires_at: entry.expires_at.map(DateTime::from),
+            schedules: entry.schedules.clone(),
+            allowed_ips: entry.allowed_ips.clone(),
+        }
+    }
+}
+
+/// 許可されたMACアドレスの配列として書かれたJSONを読み込む
+///
+/// 各要素はMACアドレス (またはプレフィックス) の文字列か、付帯情報を含むオブジェクトのどちらでもよい
+pub fn read_allowed_macs(file: File) -> Result<Vec<AllowedMac>, anyhow::Error> {
+    let reader = BufReader::new(file);
+    let entries: Vec<AllowedMacFileEntry> = serde_json::from_reader(reader)?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let record = match entry {
+                AllowedMacFileEntry::Address(mac_address) => AllowedMacRecord {
+                    mac_address,
+                    label: None,
+                    owner: None,
+                    note: None,
+                    created_at: None,
+                    created_by: None,
+                    updated_at: None,
+                    expires_at: None,
+                    schedules: Vec::new(),
+                    allowed_ips: Vec::new(),
+                },
+                AllowedMacFileEntry::Record(record) => record,
+            };
+            let address = MacPrefix::from_str(&record.mac_address).map_err(|_| {
+                anyhow::anyhow!("Failed to parse mac address {:?}", record.mac_address)
+            })?;
+            Ok(AllowedMac {
+                address,
+                label: record.label,
+                owner: record.owner,
+                note: record.note,
+                created_at: record.created_at.map(SystemTime::from),
+                created_by: record.created_by,
+                updated_at: record.updated_at.map(SystemTime::from),
+                expires_at: record.expires_at.map(SystemTime::from),
+                schedules: record.schedules,
+                allowed_ips: record.allowed_ips,
+            })
+        })
+        .collect()
+}
+
+fn write_allowed_macs_atomic(path: &Path, macs: &AllowedMacStore) -> std::io::Result<()> {
+    let mut entries: Vec<&AllowedMac> = macs.values().collect();
+    entries.sort_by_key(|entry| entry.address);
+    let records: Vec<AllowedMacRecord> = entries.into_iter().map(AllowedMacRecord::from).collect();
+    write_json_atomic(path, &records)
 }
 
 #[cfg(test)]
 mod test {
+    use std::{
+        net::Ipv4Addr,
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use chrono::{NaiveDateTime, NaiveTime, Utc, Weekday};
+    use chrono_tz::Tz;
+    use ipnetwork::Ipv4Network;
     use pnet::util::MacAddr;
 
-    use super::{AllowedMacRepository, AllowedMacRepositoryForMemory};
+    use super::{
+        AllowSchedule, Allowance, AllowedMac, AllowedMacPatch, AllowedMacRepository,
+        AllowedMacRepositoryForFile, AllowedMacRepositoryForMemory, BulkOperation, BulkOutcome,
+        MacPrefix,
+    };
+    use crate::repositories::RepositoryError;
+
+    /// 現在許可されているか。ARP Probe (0.0.0.0) はどのエントリでも認められるため送信元IPアドレスは問わない
+    fn is_allowed(repo: &impl AllowedMacRepository, address: &MacAddr) -> bool {
+        repo.check(address, &Ipv4Addr::UNSPECIFIED.into(), SystemTime::now())
+            .unwrap()
+            == Allowance::Allowed
+    }
 
     #[test]
     fn allowedmac_repo_crd_scenario() {
-        let addrs = vec![
-            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x01),
-            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x02),
-            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x03),
+        let addrs: Vec<MacPrefix> = vec![
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x01).into(),
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x02).into(),
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x03).into(),
         ];
         let repo = AllowedMacRepositoryForMemory::new();
-        // add scenario (twice)
-        for _ in 0..2 {
-            for addr in addrs.iter() {
-                repo.add(addr.clone()).expect("SyncFailed");
-            }
+        // add scenario
+        for addr in addrs.iter() {
+            repo.add(AllowedMac::new(addr.clone())).expect("SyncFailed");
+        }
+        // 2回目の追加は失敗する
+        for addr in addrs.iter() {
+            assert_eq!(
+                repo.add(AllowedMac::new(*addr)),
+                Err(RepositoryError::AlreadyExists)
+            );
         }
         // get scenario
         let mut repo_content = repo.getall().unwrap();
         repo_content.sort();
         assert_eq!(repo_content, addrs);
         assert!(repo_content.contains(addrs.get(0).unwrap()));
-        assert!(!repo_content.contains(&MacAddr::new(2, 0, 0, 0, 0, 0)));
+        assert!(!repo_content.contains(&MacAddr::new(2, 0, 0, 0, 0, 0).into()));
         // remove scenario
         let removing_addr = &addrs.get(0).unwrap();
         repo.remove(&removing_addr).expect("SyncErr");
-        assert!(!repo.contains(removing_addr).expect("SyncErr"));
+        assert!(!is_allowed(&repo, &removing_addr.address()));
+        assert_eq!(repo.remove(removing_addr), Err(RepositoryError::NotFound));
         // clear scenario
         repo.clear().expect("SyncErr");
         let repo_size = repo.getall().expect("SyncErr").len();
         assert_eq!(repo_size, 0);
     }
+
+    #[test]
+    fn allowedmac_file_repo_scenario() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        let addrs: Vec<MacPrefix> = vec![
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x01).into(),
+            MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x0f, 0x02).into(),
+        ];
+        // 存在しないファイルは空として扱う
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert_eq!(repo.getall().unwrap().len(), 0);
+        // add scenario
+        for addr in addrs.iter() {
+            repo.add(AllowedMac::new(*addr)).expect("Add failed");
+        }
+        let content: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let content: Vec<&str> = content
+            .iter()
+            .map(|entry| entry["mac_address"].as_str().unwrap())
+            .collect();
+        assert_eq!(content, vec!["02:00:00:00:0f:01", "02:00:00:00:0f:02"]);
+        // 再読み込みしても同じ内容であるか
+        let reopened = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        let mut repo_content = reopened.getall().unwrap();
+        repo_content.sort();
+        assert_eq!(repo_content, addrs);
+        // remove scenario
+        repo.remove(&addrs[0]).expect("Remove failed");
+        assert_eq!(repo.remove(&addrs[0]), Err(RepositoryError::NotFound));
+        assert_eq!(
+            repo.add(AllowedMac::new(addrs[1])),
+            Err(RepositoryError::AlreadyExists)
+        );
+        let reopened = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert_eq!(reopened.getall().unwrap(), vec![addrs[1]]);
+        // clear scenario
+        repo.clear().expect("Clear failed");
+        let reopened = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert_eq!(reopened.getall().unwrap().len(), 0);
+        // 一時ファイルが残っていないか
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn allowedmac_file_repo_rollback_on_write_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 1)))
+            .expect("Add failed");
+        // 書き込み先のディレクトリを消して書き込みを失敗させる
+        dir.close().unwrap();
+        assert_eq!(
+            repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 2))),
+            Err(RepositoryError::PersistFailed)
+        );
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 2)));
+        assert_eq!(repo.getall().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn allowedmac_repo_metadata_scenario() {
+        let addr = MacPrefix::from(MacAddr::new(2, 0, 0, 0, 0xf, 1));
+        let repo = AllowedMacRepositoryForMemory::new();
+        let created = repo
+            .add(AllowedMac {
+                label: Some("printer".to_string()),
+                created_by: Some("alice".to_string()),
+                ..AllowedMac::new(addr)
+            })
+            .unwrap();
+        assert_eq!(repo.get(&addr).unwrap(), created);
+        // 指定した項目のみ変更し、空文字列の項目は削除する
+        let patch = AllowedMacPatch {
+            label: Some(String::new()),
+            owner: Some("bob".to_string()),
+            ..Default::default()
+        };
+        let modified = repo.modify(&addr, patch).unwrap();
+        assert_eq!(modified.label, None);
+        assert_eq!(modified.owner.as_deref(), Some("bob"));
+        assert_eq!(modified.created_at, created.created_at);
+        assert_eq!(modified.created_by.as_deref(), Some("alice"));
+        assert!(modified.updated_at >= created.updated_at);
+        assert_eq!(
+            repo.modify(
+                &MacAddr::new(2, 0, 0, 0, 0, 0).into(),
+                AllowedMacPatch::default()
+            ),
+            Err(RepositoryError::NotFound)
+        );
+        assert_eq!(
+            repo.get(&MacAddr::new(2, 0, 0, 0, 0, 0).into()),
+            Err(RepositoryError::NotFound)
+        );
+    }
+
+    #[test]
+    fn allowedmac_file_repo_reads_both_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        std::fs::write(
+            &path,
+            r#"[
+                "02:00:00:00:0f:01",
+                {
+                    "mac_address": "02:00:00:00:0f:02",
+                    "label": "printer",
+                    "owner": "alice",
+                    "note": "2F",
+                    "created_at": "2024-04-01T00:00:00Z",
+                    "created_by": "admin",
+                    "updated_at": "2024-04-02T00:00:00Z"
+                }
+            ]"#,
+        )
+        .unwrap();
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        let plain = repo.get(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into()).unwrap();
+        assert_eq!(plain.label, None);
+        assert_eq!(plain.created_at, None);
+        let record = repo.get(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into()).unwrap();
+        assert_eq!(record.label.as_deref(), Some("printer"));
+        assert_eq!(record.owner.as_deref(), Some("alice"));
+        assert_eq!(record.note.as_deref(), Some("2F"));
+        assert_eq!(record.created_by.as_deref(), Some("admin"));
+        assert_eq!(
+            record.created_at.map(chrono::DateTime::<chrono::Utc>::from),
+            Some("2024-04-01T00:00:00Z".parse().unwrap())
+        );
+        // 書き戻しても付帯情報が失われない
+        repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 3)))
+            .unwrap();
+        let reopened = AllowedMacRepositoryForFile::open(path).unwrap();
+        assert_eq!(
+            reopened
+                .get(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into())
+                .unwrap(),
+            record
+        );
+        assert_eq!(
+            reopened
+                .get(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into())
+                .unwrap(),
+            plain
+        );
+    }
+
+    #[test]
+    fn allowedmac_repo_expiry_scenario() {
+        let now = SystemTime::now();
+        let addrs: Vec<MacPrefix> = (1..=3)
+            .map(|i| MacAddr::new(2, 0, 0, 0, 0xf, i).into())
+            .collect();
+        let repo = AllowedMacRepositoryForMemory::new();
+        repo.add(AllowedMac::new(addrs[0])).unwrap();
+        repo.add(AllowedMac {
+            expires_at: Some(now + Duration::from_secs(3600)),
+            ..AllowedMac::new(addrs[1])
+        })
+        .unwrap();
+        repo.add(AllowedMac {
+            expires_at: Some(now - Duration::from_secs(1)),
+            ..AllowedMac::new(addrs[2])
+        })
+        .unwrap();
+        // 期限切れのエントリは削除前から許可されていないものとして扱う
+        assert!(!is_allowed(&repo, &addrs[2].address()));
+        assert_eq!(repo.get(&addrs[2]), Err(RepositoryError::NotFound));
+        assert_eq!(repo.getall_entries().unwrap().len(), 2);
+        assert_eq!(
+            repo.modify(&addrs[2], AllowedMacPatch::default()),
+            Err(RepositoryError::NotFound)
+        );
+        // 期限を過ぎたものだけが削除される
+        assert_eq!(repo.remove_expired(now).unwrap(), vec![addrs[2]]);
+        assert_eq!(
+            repo.remove_expired(now + Duration::from_secs(3600))
+                .unwrap(),
+            vec![addrs[1]]
+        );
+        assert_eq!(repo.getall().unwrap(), vec![addrs[0]]);
+        // 期限の解除
+        repo.add(AllowedMac {
+            expires_at: Some(now + Duration::from_secs(60)),
+            ..AllowedMac::new(addrs[1])
+        })
+        .unwrap();
+        let patch = AllowedMacPatch {
+            expires_at: Some(None),
+            ..Default::default()
+        };
+        assert_eq!(repo.modify(&addrs[1], patch).unwrap().expires_at, None);
+        assert!(repo
+            .remove_expired(now + Duration::from_secs(3600))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn allowedmac_file_repo_persists_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        let addr = MacPrefix::from(MacAddr::new(2, 0, 0, 0, 0xf, 1));
+        let expires_at = SystemTime::now() + Duration::from_secs(3600);
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        repo.add(AllowedMac {
+            expires_at: Some(expires_at),
+            ..AllowedMac::new(addr)
+        })
+        .unwrap();
+        let reopened = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert_eq!(reopened.get(&addr).unwrap().expires_at, Some(expires_at));
+        // 期限切れのエントリがなければファイルには触れない
+        std::fs::remove_file(&path).unwrap();
+        assert!(repo.remove_expired(SystemTime::now()).unwrap().is_empty());
+        assert!(!path.exists());
+        // 期限切れのエントリの削除もファイルに書き戻す
+        assert_eq!(repo.remove_expired(expires_at).unwrap(), vec![addr]);
+        let reopened = AllowedMacRepositoryForFile::open(path).unwrap();
+        assert!(reopened.getall().unwrap().is_empty());
+    }
+
+    #[test]
+    fn allow_schedule_contains() {
+        let at = |text: &str| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        let schedule = AllowSchedule {
+            weekdays: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+            start: NaiveTime::from_hms_opt(8, 30, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(16, 0, 0).unwrap(),
+        };
+        // 2024-04-01 は月曜日
+        assert!(!schedule.contains(at("2024-04-01 08:29")));
+        assert!(schedule.contains(at("2024-04-01 08:30")));
+        assert!(schedule.contains(at("2024-04-05 15:59")));
+        assert!(!schedule.contains(at("2024-04-05 16:00")));
+        assert!(!schedule.contains(at("2024-04-06 12:00")));
+        // 日付をまたぐ時間帯は開始する日の曜日で判定する
+        let overnight = AllowSchedule {
+            weekdays: vec![Weekday::Fri],
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(2, 0, 0).unwrap(),
+        };
+        assert!(overnight.contains(at("2024-04-05 23:00")));
+        assert!(overnight.contains(at("2024-04-06 01:59")));
+        assert!(!overnight.contains(at("2024-04-06 02:00")));
+        assert!(!overnight.contains(at("2024-04-06 23:00")));
+        assert!(!overnight.contains(at("2024-04-05 01:00")));
+    }
+
+    #[test]
+    fn allowedmac_repo_schedule_scenario() {
+        let addr = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let now = Utc::now().with_timezone(&Tz::Asia__Tokyo).naive_local();
+        let hour = chrono::Duration::hours(1);
+        // 東京の現在時刻を含む時間帯。UTCの現在時刻は9時間ずれるため含まない
+        let schedule = AllowSchedule {
+            weekdays: Vec::new(),
+            start: (now - hour).time(),
+            end: (now + hour).time(),
+        };
+        let entry = AllowedMac {
+            schedules: vec![schedule],
+            ..AllowedMac::new(addr)
+        };
+        let repo = AllowedMacRepositoryForMemory::new().with_timezone(Tz::Asia__Tokyo);
+        repo.add(entry.clone()).unwrap();
+        assert!(is_allowed(&repo, &addr));
+        let repo = AllowedMacRepositoryForMemory::new();
+        repo.add(entry).unwrap();
+        assert!(!is_allowed(&repo, &addr));
+        // 時間外でも一覧には含まれる
+        assert_eq!(repo.getall().unwrap(), vec![addr.into()]);
+        // スケジュールを外すと常に許可する
+        let patch = AllowedMacPatch {
+            schedules: Some(Vec::new()),
+            ..Default::default()
+        };
+        repo.modify(&addr.into(), patch).unwrap();
+        assert!(is_allowed(&repo, &addr));
+    }
+
+    #[test]
+    fn allowedmac_repo_prefix_scenario() {
+        let repo = AllowedMacRepositoryForMemory::new();
+        let oui = MacPrefix::from_str("00:1b:63:*").unwrap();
+        let block = MacPrefix::from_str("02:00:00:00:00:00/40").unwrap();
+        repo.add(AllowedMac::new(oui)).unwrap();
+        repo.add(AllowedMac {
+            expires_at: Some(SystemTime::now() - Duration::from_secs(1)),
+            ..AllowedMac::new(block)
+        })
+        .unwrap();
+        // 多数のMACアドレスと同時に登録できる
+        for i in 0..4096u16 {
+            let [high, low] = i.to_be_bytes();
+            repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 1, high, low)))
+                .unwrap();
+        }
+        assert!(is_allowed(
+            &repo,
+            &MacAddr::new(0x00, 0x1b, 0x63, 0x12, 0x34, 0x56)
+        ));
+        assert!(is_allowed(&repo, &MacAddr::new(2, 0, 0, 1, 0x0f, 0xff)));
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 1, 0x10, 0)));
+        // 期限切れのプレフィックスには一致しない
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0, 1)));
+        // プレフィックス単位で取得・削除する
+        assert_eq!(repo.get(&oui).unwrap().address, oui);
+        assert_eq!(
+            repo.get(&MacAddr::new(0x00, 0x1b, 0x63, 0, 0, 0).into()),
+            Err(RepositoryError::NotFound)
+        );
+        repo.remove(&oui).unwrap();
+        assert!(!is_allowed(
+            &repo,
+            &MacAddr::new(0x00, 0x1b, 0x63, 0x12, 0x34, 0x56)
+        ));
+        // 同じ長さのプレフィックスが残っている間は一致する
+        let other = MacPrefix::from_str("00:1b:64:*").unwrap();
+        repo.add(AllowedMac::new(oui)).unwrap();
+        repo.add(AllowedMac::new(other)).unwrap();
+        repo.remove(&other).unwrap();
+        assert!(is_allowed(
+            &repo,
+            &MacAddr::new(0x00, 0x1b, 0x63, 0x12, 0x34, 0x56)
+        ));
+    }
+
+    #[test]
+    fn allowedmac_file_repo_reads_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        std::fs::write(
+            &path,
+            r#"["00:1b:63:*", { "mac_address": "02:00:00:00:00:00/44", "label": "vm" }]"#,
+        )
+        .unwrap();
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert!(is_allowed(&repo, &MacAddr::new(0, 0x1b, 0x63, 1, 2, 3)));
+        assert!(is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0, 0x0f)));
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0, 0x10)));
+        repo.clear().unwrap();
+        repo.add(AllowedMac::new(MacPrefix::from_str("00:1b:63:*").unwrap()))
+            .unwrap();
+        let content: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(content[0]["mac_address"], "00:1b:63:*");
+    }
+
+    #[test]
+    fn allowedmac_repo_ip_binding_scenario() {
+        let repo = AllowedMacRepositoryForMemory::new();
+        let addr = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let other = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        repo.add(AllowedMac {
+            allowed_ips: vec![
+                Ipv4Network::from_str("192.168.0.10").unwrap(),
+                Ipv4Network::from_str("10.0.1.0/24").unwrap(),
+            ],
+            ..AllowedMac::new(addr)
+        })
+        .unwrap();
+        repo.add(AllowedMac::new(other)).unwrap();
+        let cases = [
+            (addr, Ipv4Addr::new(192, 168, 0, 10), Allowance::Allowed),
+            (addr, Ipv4Addr::new(10, 0, 1, 200), Allowance::Allowed),
+            (
+                addr,
+                Ipv4Addr::new(192, 168, 0, 11),
+                Allowance::UnexpectedIp,
+            ),
+            // ARP Probe は許可する
+            (addr, Ipv4Addr::UNSPECIFIED, Allowance::Allowed),
+            (other, Ipv4Addr::new(192, 168, 0, 11), Allowance::Allowed),
+            (
+                MacAddr::new(2, 0, 0, 0, 0xf, 3),
+                Ipv4Addr::new(192, 168, 0, 10),
+                Allowance::NotAllowed,
+            ),
+        ];
+        for (mac, ip, expected) in cases {
+            assert_eq!(
+                repo.check(&mac, &ip.into(), SystemTime::now()).unwrap(),
+                expected,
+                "{} {}",
+                mac,
+                ip
+            );
+        }
+        // IPアドレスの制限があってもMACアドレスとしては許可されている
+        assert!(is_allowed(&repo, &addr));
+        // 一致するプレフィックスのいずれかが認めていれば許可する
+        repo.add(AllowedMac::new(MacPrefix::from_str("02:00:00:*").unwrap()))
+            .unwrap();
+        assert_eq!(
+            repo.check(
+                &addr,
+                &Ipv4Addr::new(192, 168, 0, 11).into(),
+                SystemTime::now()
+            )
+            .unwrap(),
+            Allowance::Allowed
+        );
+        repo.remove(&MacPrefix::from_str("02:00:00:*").unwrap())
+            .unwrap();
+        // 空にすると制限を外す
+        let patch = AllowedMacPatch {
+            allowed_ips: Some(Vec::new()),
+            ..Default::default()
+        };
+        repo.modify(&addr.into(), patch).unwrap();
+        assert_eq!(
+            repo.check(
+                &addr,
+                &Ipv4Addr::new(192, 168, 0, 11).into(),
+                SystemTime::now()
+            )
+            .unwrap(),
+            Allowance::Allowed
+        );
+    }
+
+    #[test]
+    fn allowedmac_file_repo_persists_allowed_ips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        let addr = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        repo.add(AllowedMac {
+            allowed_ips: vec![Ipv4Network::from_str("10.0.1.0/24").unwrap()],
+            ..AllowedMac::new(addr)
+        })
+        .unwrap();
+        let content: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            content[0]["allowed_ips"],
+            serde_json::json!(["10.0.1.0/24"])
+        );
+        let repo = AllowedMacRepositoryForFile::open(path).unwrap();
+        assert_eq!(
+            repo.check(&addr, &Ipv4Addr::new(10, 0, 2, 1).into(), SystemTime::now())
+                .unwrap(),
+            Allowance::UnexpectedIp
+        );
+    }
+
+    #[test]
+    fn allowedmac_file_repo_rejects_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        std::fs::write(&path, r#"["02:00:00:00:0f:01", "hello, world"]"#).unwrap();
+        assert!(AllowedMacRepositoryForFile::open(path).is_err());
+    }
+
+    #[test]
+    fn allowedmac_repo_bulk_scenario() {
+        let addrs: Vec<MacPrefix> = (1..=4)
+            .map(|i| MacAddr::new(2, 0, 0, 0, 0xf, i).into())
+            .collect();
+        let entries: Vec<AllowedMac> = addrs.iter().map(|addr| AllowedMac::new(*addr)).collect();
+        let repo = AllowedMacRepositoryForMemory::new();
+        repo.add(AllowedMac::new(addrs[0])).unwrap();
+        // add scenario
+        let result = repo.bulk(BulkOperation::Add, &entries[0..3]).unwrap();
+        assert_eq!(
+            result.outcomes,
+            vec![
+                (addrs[0], BulkOutcome::Unchanged),
+                (addrs[1], BulkOutcome::Added),
+                (addrs[2], BulkOutcome::Added),
+            ]
+        );
+        assert!(result.removed.is_empty());
+        // remove scenario
+        let result = repo.bulk(BulkOperation::Remove, &entries[2..4]).unwrap();
+        assert_eq!(
+            result.outcomes,
+            vec![
+                (addrs[2], BulkOutcome::Removed),
+                (addrs[3], BulkOutcome::NotFound),
+            ]
+        );
+        // replace scenario
+        let result = repo.bulk(BulkOperation::Replace, &entries[1..4]).unwrap();
+        assert_eq!(
+            result.outcomes,
+            vec![
+                (addrs[1], BulkOutcome::Unchanged),
+                (addrs[2], BulkOutcome::Added),
+                (addrs[3], BulkOutcome::Added),
+            ]
+        );
+        assert_eq!(result.removed, vec![addrs[0]]);
+        let mut repo_content = repo.getall().unwrap();
+        repo_content.sort();
+        assert_eq!(repo_content, addrs[1..4]);
+    }
+
+    #[test]
+    fn allowedmac_file_repo_bulk_is_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowed_macs.json");
+        let addrs: Vec<MacAddr> = (1..=3).map(|i| MacAddr::new(2, 0, 0, 0, 0xf, i)).collect();
+        let entries: Vec<AllowedMac> = addrs.iter().map(|addr| AllowedMac::new(*addr)).collect();
+        let repo = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        repo.bulk(BulkOperation::Add, &entries).unwrap();
+        let reopened = AllowedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert_eq!(reopened.getall().unwrap().len(), 3);
+        // 書き込みに失敗した場合は1件も反映しない
+        dir.close().unwrap();
+        assert_eq!(
+            repo.bulk(BulkOperation::Replace, &entries[0..1]),
+            Err(RepositoryError::PersistFailed)
+        );
+        assert_eq!(repo.getall().unwrap().len(), 3);
+    }
 }
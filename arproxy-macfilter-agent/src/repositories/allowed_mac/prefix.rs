@@ -0,0 +1,188 @@
+use std::{fmt, str::FromStr};
+
+use pnet::util::MacAddr;
+
+/// MACアドレスのビット数
+pub const MAC_BITS: u8 = 48;
+
+/// MACアドレスのプレフィックス。長さが48のものは単一のMACアドレスを表す
+///
+/// `00:1b:63:*` のようなオクテット単位の表記と、`02:00:00:00:00:00/40` のような長さを指定する表記を受け付ける
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacPrefix {
+    bits: u64,
+    length: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid MAC address or prefix")]
+pub struct ParseMacPrefixError;
+
+fn to_bits(address: MacAddr) -> u64 {
+    address
+        .octets()
+        .iter()
+        .fold(0, |bits, octet| (bits << 8) | u64::from(*octet))
+}
+
+fn mask(length: u8) -> u64 {
+    let all = (1u64 << MAC_BITS) - 1;
+    all & !(all >> length)
+}
+
+impl MacPrefix {
+    /// `address` の先頭 `length` ビットからなるプレフィックス。`length` は48以下に丸める
+    pub fn new(address: MacAddr, length: u8) -> Self {
+        let length = length.min(MAC_BITS);
+        Self {
+            bits: to_bits(address) & mask(length),
+            length,
+        }
+    }
+
+    pub fn address(&self) -> MacAddr {
+        let octets = self.bits.to_be_bytes();
+        MacAddr::new(
+            octets[2], octets[3], octets[4], octets[5], octets[6], octets[7],
+        )
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    pub fn matches(&self, address: &MacAddr) -> bool {
+        to_bits(*address) & mask(self.length) == self.bits
+    }
+}
+
+impl From<MacAddr> for MacPrefix {
+    fn from(address: MacAddr) -> Self {
+        Self::new(address, MAC_BITS)
+    }
+}
+
+impl fmt::Display for MacPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.length == MAC_BITS {
+            return write!(f, "{}", self.address());
+        }
+        if !self.length.is_multiple_of(8) {
+            return write!(f, "{}/{}", self.address(), self.length);
+        }
+        let octets = self.address().octets();
+        for octet in &octets[..usize::from(self.length / 8)] {
+            write!(f, "{:02x}:", octet)?;
+        }
+        write!(f, "*")
+    }
+}
+
+impl FromStr for MacPrefix {
+    type Err = ParseMacPrefixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((address, length)) = s.split_once('/') {
+            let address = MacAddr::from_str(address).map_err(|_| ParseMacPrefixError)?;
+            let length: u8 = length.parse().map_err(|_| ParseMacPrefixError)?;
+            if length > MAC_BITS {
+                return Err(ParseMacPrefixError);
+            }
+            let prefix = Self::new(address, length);
+            // プレフィックスより後ろのビットが立っている場合は誤りとみなす
+            if prefix.address() != address {
+                return Err(ParseMacPrefixError);
+            }
+            return Ok(prefix);
+        }
+        if let Some(octets) = s.strip_suffix('*') {
+            let octets = match octets {
+                "" => Vec::new(),
+                octets => octets
+                    .strip_suffix(':')
+                    .ok_or(ParseMacPrefixError)?
+                    .split(':')
+                    .map(|octet| {
+                        if (1..=2).contains(&octet.len()) {
+                            u8::from_str_radix(octet, 16).map_err(|_| ParseMacPrefixError)
+                        } else {
+                            Err(ParseMacPrefixError)
+                        }
+                    })
+                    .collect::<Result<Vec<u8>, _>>()?,
+            };
+            if octets.len() >= 6 {
+                return Err(ParseMacPrefixError);
+            }
+            let mut address = [0u8; 6];
+            address[..octets.len()].copy_from_slice(&octets);
+            let [a, b, c, d, e, f] = address;
+            return Ok(Self::new(
+                MacAddr::new(a, b, c, d, e, f),
+                octets.len() as u8 * 8,
+            ));
+        }
+        MacAddr::from_str(s)
+            .map(Self::from)
+            .map_err(|_| ParseMacPrefixError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use pnet::util::MacAddr;
+
+    use super::MacPrefix;
+
+    #[test]
+    fn parse_and_format_prefix() {
+        let cases = [
+            ("02:00:00:00:0f:01", "02:00:00:00:0f:01", 48),
+            ("00:1B:63:*", "00:1b:63:*", 24),
+            ("0:1b:63:*", "00:1b:63:*", 24),
+            ("*", "*", 0),
+            ("02:00:00:00:00:00/40", "02:00:00:00:00:*", 40),
+            ("02:00:00:00:00:00/36", "02:00:00:00:00:00/36", 36),
+            ("02:00:00:00:0f:01/48", "02:00:00:00:0f:01", 48),
+        ];
+        for (text, expected, length) in cases {
+            let prefix = MacPrefix::from_str(text).unwrap();
+            assert_eq!(prefix.to_string(), expected, "{}", text);
+            assert_eq!(prefix.length(), length, "{}", text);
+            assert_eq!(MacPrefix::from_str(expected).unwrap(), prefix);
+        }
+        let invalid = [
+            "hello, world",
+            "00:1b:63",
+            "00:1b:63*",
+            "00:1b:63:*:*",
+            "00:1b:63:00:00:00:*",
+            "00:1b:zz:*",
+            "00:1b:063:*",
+            "02:00:00:00:00:00/49",
+            "02:00:00:00:00:01/40",
+            "02:00:00:00:00:00/",
+        ];
+        for text in invalid {
+            assert!(MacPrefix::from_str(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn prefix_matches() {
+        let oui = MacPrefix::from_str("00:1b:63:*").unwrap();
+        assert!(oui.matches(&MacAddr::new(0x00, 0x1b, 0x63, 0x12, 0x34, 0x56)));
+        assert!(!oui.matches(&MacAddr::new(0x00, 0x1b, 0x64, 0x12, 0x34, 0x56)));
+        let block = MacPrefix::from_str("02:00:00:00:00:00/44").unwrap();
+        assert!(block.matches(&MacAddr::new(2, 0, 0, 0, 0, 0x0f)));
+        assert!(!block.matches(&MacAddr::new(2, 0, 0, 0, 0, 0x10)));
+        let exact = MacPrefix::from(MacAddr::new(2, 0, 0, 0, 0, 1));
+        assert!(exact.matches(&MacAddr::new(2, 0, 0, 0, 0, 1)));
+        assert!(!exact.matches(&MacAddr::new(2, 0, 0, 0, 0, 2)));
+        assert!(MacPrefix::from_str("*")
+            .unwrap()
+            .matches(&MacAddr::new(2, 0, 0, 0, 0, 2)));
+    }
+}
@@ -1,7 +1,9 @@
 use pnet::util::MacAddr;
 use std::{
     collections::HashMap,
-    net::Ipv4Addr,
+    fmt::Debug,
+    hash::Hash,
+    net::{Ipv4Addr, Ipv6Addr},
     sync::{Arc, RwLock},
     time::{Duration, SystemTime},
 };
@@ -9,17 +11,26 @@ use tracing::{debug, trace};
 
 use super::RepositoryError;
 
+/// ログに記録するプロトコルアドレス (IPv4 / IPv6)
+pub trait ProtocolAddr: Copy + Eq + Hash + Debug + Send + Sync + 'static {}
+
+impl ProtocolAddr for Ipv4Addr {}
+impl ProtocolAddr for Ipv6Addr {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ArpLog {
+pub struct ArpLog<I: ProtocolAddr = Ipv4Addr> {
     pub sender_mac: MacAddr,
-    pub sender_ip: Ipv4Addr,
+    pub sender_ip: I,
     // pub target_mac: MacAddr, target mac address always all-zero
-    pub target_ip: Ipv4Addr,
+    pub target_ip: I,
     pub last_seen: SystemTime,
 }
 
-impl ArpLog {
-    pub fn new(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+/// IPv6 Neighbor Solicitationのログ。ArpLogと同じ形式で別のレポジトリに記録する
+pub type NdpLog = ArpLog<Ipv6Addr>;
+
+impl<I: ProtocolAddr> ArpLog<I> {
+    pub fn new(sender_mac: MacAddr, sender_ip: I, target_ip: I) -> Self {
         Self {
             sender_mac,
             sender_ip,
@@ -29,58 +40,49 @@ impl ArpLog {
     }
 }
 
-pub trait ArpLogRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+pub trait ArpLogRepository<I: ProtocolAddr = Ipv4Addr>:
+    Clone + std::marker::Send + std::marker::Sync + 'static
+{
     /// ArpLogを挿入またはlast_seenを更新する
     ///
-    fn put(&self, arplog: ArpLog) -> Result<(), RepositoryError>;
+    fn put(&self, arplog: ArpLog<I>) -> Result<(), RepositoryError>;
     /// 全てのArpLogを取得し、同時にdurationを超過したものは削除する
-    fn getall_autoclear(&self, duration: Duration) -> Result<Vec<ArpLog>, RepositoryError>;
-    fn getall_without_autoclear(&self) -> Result<Vec<ArpLog>, RepositoryError>;
+    fn getall_autoclear(&self, duration: Duration) -> Result<Vec<ArpLog<I>>, RepositoryError>;
+    fn getall_without_autoclear(&self) -> Result<Vec<ArpLog<I>>, RepositoryError>;
     fn remove(&self, address: &MacAddr) -> Result<(), RepositoryError>;
+    /// 送信元MACアドレスと目標IPアドレスの組に一致するArpLogを削除する
+    fn remove_target(&self, address: &MacAddr, target_ip: &I) -> Result<(), RepositoryError>;
     fn clear(&self) -> Result<(), RepositoryError>;
 }
 
 #[derive(Debug, Clone)]
-struct ArpLogForMemory {
+struct ArpLogForMemory<I: ProtocolAddr> {
     pub sender_mac: MacAddr,
-    pub sender_ip: Ipv4Addr,
+    pub sender_ip: I,
     // pub target_mac: MacAddr, target mac address always all-zero
     // pub target_ips: Vec<(Ipv4Addr, SystemTime)>,
-    pub target_ips: HashMap<Ipv4Addr, SystemTime>,
+    pub target_ips: HashMap<I, SystemTime>,
     pub last_seen: SystemTime,
 }
 
-impl ArpLogForMemory {
-    fn to_arplogs_autoclear(&mut self, duration: Duration) -> Vec<ArpLog> {
-        let mut result = Vec::new();
-        let mut template = ArpLog {
+impl<I: ProtocolAddr> ArpLogForMemory<I> {
+    fn arplog(&self, target_ip: I, last_seen: SystemTime) -> ArpLog<I> {
+        ArpLog {
             sender_mac: self.sender_mac,
             sender_ip: self.sender_ip,
-            target_ip: Ipv4Addr::new(0, 0, 0, 0),
-            last_seen: SystemTime::now(),
-        };
-        /* Vector Implementation
-        let mut i = 0;
-        while(i < self.target_ips.len()){
-            let (tip, time)= self.target_ips[i];
-            if(time.elapsed().unwrap() < duration){
-                template.target_ip = tip.clone();
-                template.last_seen = time.clone();
-                result.push(template.clone());
-                i += 1;
-            } else {
-                self.target_ips.remove(i);
-            }
+            target_ip,
+            last_seen,
         }
-         */
+    }
+
+    fn collect_arplogs_autoclear(&mut self, duration: Duration) -> Vec<ArpLog<I>> {
+        let mut result = Vec::new();
         let mut removing = Vec::new();
         for (tip, time) in self.target_ips.iter() {
             if time.elapsed().unwrap() < duration {
-                template.target_ip = tip.clone();
-                template.last_seen = time.clone();
-                result.push(template.clone());
+                result.push(self.arplog(*tip, *time));
             } else {
-                removing.push(tip.clone());
+                removing.push(*tip);
             }
         }
 
@@ -90,29 +92,23 @@ impl ArpLogForMemory {
         result
     }
 
-    fn to_arplog(&self) -> Vec<ArpLog> {
-        let mut result = Vec::new();
-        let mut template = ArpLog {
-            sender_mac: self.sender_mac,
-            sender_ip: self.sender_ip,
-            target_ip: Ipv4Addr::new(0, 0, 0, 0),
-            last_seen: SystemTime::now(),
-        };
-        for (tip, time) in self.target_ips.iter() {
-            template.target_ip = tip.clone();
-            template.last_seen = time.clone();
-            result.push(template.clone());
-        }
-        result
+    fn to_arplog(&self) -> Vec<ArpLog<I>> {
+        self.target_ips
+            .iter()
+            .map(|(tip, time)| self.arplog(*tip, *time))
+            .collect()
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ArpLogRepositoryForMemory {
-    store: Arc<RwLock<HashMap<MacAddr, ArpLogForMemory>>>,
+pub struct ArpLogRepositoryForMemory<I: ProtocolAddr = Ipv4Addr> {
+    store: Arc<RwLock<HashMap<MacAddr, ArpLogForMemory<I>>>>,
 }
 
-impl ArpLogRepositoryForMemory {
+/// NdpLogを記録するレポジトリ
+pub type NdpLogRepositoryForMemory = ArpLogRepositoryForMemory<Ipv6Addr>;
+
+impl<I: ProtocolAddr> ArpLogRepositoryForMemory<I> {
     pub fn new() -> Self {
         Self {
             store: Arc::default(),
@@ -120,8 +116,8 @@ impl ArpLogRepositoryForMemory {
     }
 }
 
-impl ArpLogRepository for ArpLogRepositoryForMemory {
-    fn put(&self, arplog: ArpLog) -> Result<(), RepositoryError> {
+impl<I: ProtocolAddr> ArpLogRepository<I> for ArpLogRepositoryForMemory<I> {
+    fn put(&self, arplog: ArpLog<I>) -> Result<(), RepositoryError> {
         trace!("ArpLog putted: {:?}", arplog);
         if let Ok(mut store) = self.store.write() {
             if let Some(alfm) = store.get_mut(&arplog.sender_mac) {
@@ -147,16 +143,16 @@ impl ArpLogRepository for ArpLogRepositoryForMemory {
         }
     }
 
-    fn getall_autoclear(&self, duration: Duration) -> Result<Vec<ArpLog>, RepositoryError> {
+    fn getall_autoclear(&self, duration: Duration) -> Result<Vec<ArpLog<I>>, RepositoryError> {
         if let Ok(mut store) = self.store.write() {
             let mut result = Vec::new();
             let mut removing = Vec::new();
 
             for (smac, arplog) in store.iter_mut() {
                 if arplog.last_seen.elapsed().unwrap_or(duration) <= duration {
-                    result.append(&mut arplog.to_arplogs_autoclear(duration));
+                    result.append(&mut arplog.collect_arplogs_autoclear(duration));
                 } else {
-                    removing.push(smac.clone());
+                    removing.push(*smac);
                 }
             }
 
@@ -169,7 +165,7 @@ impl ArpLogRepository for ArpLogRepositoryForMemory {
         }
     }
 
-    fn getall_without_autoclear(&self) -> Result<Vec<ArpLog>, RepositoryError> {
+    fn getall_without_autoclear(&self) -> Result<Vec<ArpLog<I>>, RepositoryError> {
         if let Ok(store) = self.store.read() {
             let mut result = Vec::new();
             for (_, arplog) in store.iter() {
@@ -199,4 +195,71 @@ impl ArpLogRepository for ArpLogRepositoryForMemory {
             Err(RepositoryError::SyncFailed)
         }
     }
+
+    fn remove_target(&self, address: &MacAddr, target_ip: &I) -> Result<(), RepositoryError> {
+        debug!("Removing ArpLog: {} -> {:?}", address, target_ip);
+        if let Ok(mut store) = self.store.write() {
+            if let Some(alfm) = store.get_mut(address) {
+                alfm.target_ips.remove(target_ip);
+                if alfm.target_ips.is_empty() {
+                    store.remove(address);
+                }
+            }
+            Ok(())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::Ipv6Addr, str::FromStr, time::Duration};
+
+    use pnet::util::MacAddr;
+
+    use super::{ArpLogRepository, NdpLog, NdpLogRepositoryForMemory};
+
+    #[test]
+    fn ndplog_repo_scenario() {
+        let repo = NdpLogRepositoryForMemory::new();
+        let sender_mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let sender_ip = Ipv6Addr::from_str("fe80::1").unwrap();
+        let target_ips = [
+            Ipv6Addr::from_str("fe80::2").unwrap(),
+            Ipv6Addr::from_str("fe80::3").unwrap(),
+        ];
+        // put scenario (twice)
+        for _ in 0..2 {
+            for target_ip in target_ips {
+                repo.put(NdpLog::new(sender_mac, sender_ip, target_ip))
+                    .expect("SyncErr");
+            }
+        }
+        let ndplogs = repo.getall_autoclear(Duration::from_secs(60)).unwrap();
+        assert_eq!(ndplogs.len(), 2);
+        assert!(ndplogs.iter().all(|ndplog| ndplog.sender_ip == sender_ip));
+        // durationを超過したものは削除される
+        let ndplogs = repo.getall_autoclear(Duration::ZERO).unwrap();
+        assert_eq!(ndplogs.len(), 0);
+        assert_eq!(repo.getall_without_autoclear().unwrap().len(), 0);
+        // remove scenario
+        repo.put(NdpLog::new(sender_mac, sender_ip, target_ips[0]))
+            .expect("SyncErr");
+        repo.remove(&sender_mac).expect("SyncErr");
+        assert_eq!(repo.getall_without_autoclear().unwrap().len(), 0);
+        // remove_target scenario
+        for target_ip in target_ips {
+            repo.put(NdpLog::new(sender_mac, sender_ip, target_ip))
+                .expect("SyncErr");
+        }
+        repo.remove_target(&sender_mac, &target_ips[0])
+            .expect("SyncErr");
+        let ndplogs = repo.getall_without_autoclear().unwrap();
+        assert_eq!(ndplogs.len(), 1);
+        assert_eq!(ndplogs[0].target_ip, target_ips[1]);
+        repo.remove_target(&sender_mac, &target_ips[1])
+            .expect("SyncErr");
+        assert_eq!(repo.getall_without_autoclear().unwrap().len(), 0);
+    }
 }
@@ -1,26 +1,280 @@
 use crate::config;
-use std::sync::{Arc, RwLock};
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use tokio::sync::watch;
+use tracing::error;
+use validator::Validate;
+
+use super::{write_json_atomic, RepositoryError};
 
 pub trait ConfigRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
     fn get_config(&self) -> config::Config;
+    /// 設定を変更し、変更後の設定を返す。検証に失敗した場合は変更しない
+    fn update<F>(&self, f: F) -> Result<config::Config, RepositoryError>
+    where
+        F: FnOnce(&mut config::Config);
+    /// 設定の変更を受け取るReceiverを作成する
+    fn subscribe(&self) -> watch::Receiver<config::Config>;
 }
 
 #[derive(Clone, Debug)]
 pub struct ConfigRepositoryForMemory {
     store: Arc<RwLock<config::Config>>,
+    changes: Arc<watch::Sender<config::Config>>,
 }
 
 impl ConfigRepositoryForMemory {
     pub fn new(config: config::Config) -> Self {
+        let (changes, _) = watch::channel(config.clone());
         Self {
             store: Arc::new(RwLock::new(config)),
+            changes: Arc::new(changes),
         }
     }
 }
 
+/// 変更を適用した設定を検証して返す
+fn apply<F>(config: &config::Config, f: F) -> Result<config::Config, RepositoryError>
+where
+    F: FnOnce(&mut config::Config),
+{
+    let mut next = config.clone();
+    f(&mut next);
+    next.validate()
+        .map_err(|e| RepositoryError::InvalidValue(e.to_string()))?;
+    Ok(next)
+}
+
 impl ConfigRepository for ConfigRepositoryForMemory {
     // うまく排他制御できてなさそうである。
     fn get_config(&self) -> config::Config {
         self.store.read().unwrap().clone()
     }
+
+    fn update<F>(&self, f: F) -> Result<config::Config, RepositoryError>
+    where
+        F: FnOnce(&mut config::Config),
+    {
+        if let Ok(mut store) = self.store.write() {
+            let next = apply(&store, f)?;
+            *store = next.clone();
+            self.changes.send_replace(next.clone());
+            Ok(next)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn subscribe(&self) -> watch::Receiver<config::Config> {
+        self.changes.subscribe()
+    }
+}
+
+/// `before` から `after` への変更を `content` に反映する
+///
+/// オブジェクトは項目ごとに反映し、変更していない項目や `content` にしかない項目はそのまま残す
+fn merge_changes(content: &mut Value, before: &Value, after: &Value) {
+    match (content, before, after) {
+        (Value::Object(content), Value::Object(before), Value::Object(after)) => {
+            for (key, value) in after {
+                match (content.get_mut(key), before.get(key)) {
+                    (_, Some(old)) if old == value => {}
+                    (Some(current), Some(old)) => merge_changes(current, old, value),
+                    _ => {
+                        content.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            for key in before.keys().filter(|key| !after.contains_key(*key)) {
+                content.remove(key);
+            }
+        }
+        (content, _, after) => *content = after.clone(),
+    }
+}
+
+/// 変更を設定ファイルに書き戻すレポジトリ
+///
+/// 変更した項目だけをファイルの内容に反映するため、未知の項目や省略された項目はそのまま残る。
+/// 書き込みに失敗した場合は変更を取り消す。
+#[derive(Clone, Debug)]
+pub struct ConfigRepositoryForFile {
+    store: Arc<RwLock<config::Config>>,
+    changes: Arc<watch::Sender<config::Config>>,
+    path: Arc<PathBuf>,
+}
+
+impl ConfigRepositoryForFile {
+    pub fn new(config: config::Config, path: PathBuf) -> Self {
+        let (changes, _) = watch::channel(config.clone());
+        Self {
+            store: Arc::new(RwLock::new(config)),
+            changes: Arc::new(changes),
+            path: Arc::new(path),
+        }
+    }
+
+    /// ファイルが存在しない場合は変更前の設定全体を元にする
+    fn write_back(&self, before: &config::Config, after: &config::Config) -> std::io::Result<()> {
+        let before = serde_json::to_value(before)?;
+        let mut content = match File::open(&*self.path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => before.clone(),
+            Err(e) => return Err(e),
+        };
+        merge_changes(&mut content, &before, &serde_json::to_value(after)?);
+        write_json_atomic(&self.path, &content)
+    }
+}
+
+impl ConfigRepository for ConfigRepositoryForFile {
+    fn get_config(&self) -> config::Config {
+        self.store.read().unwrap().clone()
+    }
+
+    fn update<F>(&self, f: F) -> Result<config::Config, RepositoryError>
+    where
+        F: FnOnce(&mut config::Config),
+    {
+        if let Ok(mut store) = self.store.write() {
+            let next = apply(&store, f)?;
+            self.write_back(&store, &next).map_err(|e| {
+                error!("Failed to write config file {:?}: {}", self.path, e);
+                RepositoryError::PersistFailed
+            })?;
+            *store = next.clone();
+            self.changes.send_replace(next.clone());
+            Ok(next)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn subscribe(&self) -> watch::Receiver<config::Config> {
+        self.changes.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, net::Ipv4Addr};
+
+    use tempfile::tempdir;
+
+    use super::{ConfigRepository, ConfigRepositoryForFile, ConfigRepositoryForMemory};
+    use crate::{
+        config::{load_config, AdministrationConfig, ArpProxyConfig, Config},
+        repositories::RepositoryError,
+    };
+
+    fn create_dummy_config() -> Config {
+        Config {
+            interface: "eth0".to_string(),
+            allowed_mac_list: None,
+            denied_mac_list: None,
+            timezone: None,
+            arp_proxy: ArpProxyConfig {
+                mode: Default::default(),
+                proxy_allowed_macs: false,
+                arp_reply_interval: 5,
+                arp_reply_duration: 60,
+            },
+            duplicate_detection: Default::default(),
+            administration: AdministrationConfig {
+                enable_api: true,
+                enable_tcp: true,
+                listen_address: Ipv4Addr::LOCALHOST,
+                listen_port: 3000,
+                auth: None,
+                tls: None,
+                client_ca: None,
+                unix_socket: None,
+                write_back_config: false,
+            },
+        }
+    }
+
+    #[test]
+    fn config_repo_update() {
+        let repo = ConfigRepositoryForMemory::new(create_dummy_config());
+        let mut changes = repo.subscribe();
+        let config = repo
+            .update(|config| config.arp_proxy.arp_reply_interval = 10)
+            .unwrap();
+        assert_eq!(config.arp_proxy.arp_reply_interval, 10);
+        assert_eq!(repo.get_config().arp_proxy.arp_reply_interval, 10);
+        // 変更が通知される
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(changes.borrow_and_update().arp_proxy.arp_reply_interval, 10);
+        // 検証に失敗した場合は変更しない
+        for invalid in [0, 61] {
+            let result = repo.update(|config| config.arp_proxy.arp_reply_interval = invalid);
+            assert!(matches!(result, Err(RepositoryError::InvalidValue(_))));
+        }
+        assert_eq!(repo.get_config().arp_proxy.arp_reply_interval, 10);
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn config_repo_write_back() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let repo = ConfigRepositoryForFile::new(create_dummy_config(), path.clone());
+        repo.update(|config| config.arp_proxy.proxy_allowed_macs = true)
+            .unwrap();
+        let written = load_config(&path).unwrap();
+        assert!(written.arp_proxy.proxy_allowed_macs);
+        assert_eq!(written.interface, "eth0");
+        // 書き込みに失敗した場合は変更を取り消す
+        drop(dir);
+        let result = repo.update(|config| config.arp_proxy.arp_reply_duration = 120);
+        assert_eq!(result.unwrap_err(), RepositoryError::PersistFailed);
+        assert_eq!(repo.get_config().arp_proxy.arp_reply_duration, 60);
+        assert!(File::open(&path).is_err());
+    }
+
+    #[test]
+    fn config_repo_write_back_keeps_file_content() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "interface": "eth0",
+                "comment": "managed by hand",
+                "arp_proxy": { "proxy_allowed_macs": false, "arp_reply_interval": 5, "arp_reply_duration": 60, "note": "keep" },
+                "administration": { "enable_api": true, "listen_address": "127.0.0.1", "listen_port": 3000 }
+            }"#,
+        )
+        .unwrap();
+        // ファイル以外から与えられた値は書き戻さない
+        let mut config = load_config(&path).unwrap();
+        config.interface = "eth1".to_string();
+        let repo = ConfigRepositoryForFile::new(config, path.clone());
+        repo.update(|config| config.arp_proxy.proxy_allowed_macs = true)
+            .unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "interface": "eth0",
+                "comment": "managed by hand",
+                "arp_proxy": { "proxy_allowed_macs": true, "arp_reply_interval": 5, "arp_reply_duration": 60, "note": "keep" },
+                "administration": { "enable_api": true, "listen_address": "127.0.0.1", "listen_port": 3000 }
+            })
+        );
+        // 項目の順序も保持する
+        let keys: Vec<&String> = written.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            ["interface", "comment", "arp_proxy", "administration"]
+        );
+    }
 }
@@ -0,0 +1,358 @@
+use chrono::{DateTime, Utc};
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tracing::{debug, error, info};
+
+use super::{allowed_mac::MacPrefix, write_json_atomic, RepositoryError};
+
+/// 拒否リストモードで偽装の対象とするMACアドレス (またはプレフィックス)
+pub trait DeniedMacRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// いずれかのエントリに一致するか
+    fn contains(&self, address: &MacAddr) -> Result<bool, RepositoryError>;
+    fn get(&self, address: &MacPrefix) -> Result<DeniedMac, RepositoryError>;
+    fn getall(&self) -> Result<Vec<DeniedMac>, RepositoryError>;
+    /// 既に登録されている場合は `AlreadyExists`
+    fn add(&self, entry: DeniedMac) -> Result<DeniedMac, RepositoryError>;
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError>;
+}
+
+/// 拒否リストの1件と付帯情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeniedMac {
+    pub address: MacPrefix,
+    pub label: Option<String>,
+    pub note: Option<String>,
+    pub created_at: Option<SystemTime>,
+    pub created_by: Option<String>,
+}
+
+impl DeniedMac {
+    pub fn new(address: impl Into<MacPrefix>) -> Self {
+        Self {
+            address: address.into(),
+            label: None,
+            note: None,
+            created_at: None,
+            created_by: None,
+        }
+    }
+}
+
+type DeniedMacStore = BTreeMap<MacPrefix, DeniedMac>;
+
+/// 拒否リストは少数のエントリを想定し、全てのプレフィックスと照合する
+fn is_denied(store: &DeniedMacStore, address: &MacAddr) -> bool {
+    store.keys().any(|prefix| prefix.matches(address))
+}
+
+fn insert(store: &mut DeniedMacStore, mut entry: DeniedMac) -> Result<DeniedMac, RepositoryError> {
+    if store.contains_key(&entry.address) {
+        return Err(RepositoryError::AlreadyExists);
+    }
+    entry.created_at.get_or_insert_with(SystemTime::now);
+    store.insert(entry.address, entry.clone());
+    Ok(entry)
+}
+
+#[derive(Debug, Clone)]
+pub struct DeniedMacRepositoryForMemory {
+    store: Arc<RwLock<DeniedMacStore>>,
+}
+
+impl DeniedMacRepositoryForMemory {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::default(),
+        }
+    }
+}
+
+impl DeniedMacRepository for DeniedMacRepositoryForMemory {
+    fn contains(&self, address: &MacAddr) -> Result<bool, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(is_denied(&store, address))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn get(&self, address: &MacPrefix) -> Result<DeniedMac, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            store.get(address).cloned().ok_or(RepositoryError::NotFound)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<DeniedMac>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(store.values().cloned().collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn add(&self, entry: DeniedMac) -> Result<DeniedMac, RepositoryError> {
+        debug!("MAC address putted to DeniedMacRepository: {:?}", entry);
+        if let Ok(mut store) = self.store.write() {
+            insert(&mut store, entry)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError> {
+        if let Ok(mut store) = self.store.write() {
+            store
+                .remove(address)
+                .map(|_| ())
+                .ok_or(RepositoryError::NotFound)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+/// 拒否リストをJSONファイルに永続化するレポジトリ。書き込みに失敗した場合は変更を取り消す
+#[derive(Debug, Clone)]
+pub struct DeniedMacRepositoryForFile {
+    store: Arc<RwLock<DeniedMacStore>>,
+    path: Arc<PathBuf>,
+}
+
+impl DeniedMacRepositoryForFile {
+    /// ファイルを読み込んでレポジトリを作成する。ファイルが存在しない場合は空とする
+    pub fn open(path: PathBuf) -> Result<Self, anyhow::Error> {
+        let macs = match File::open(&path) {
+            Ok(file) => read_denied_macs(file)?
+                .into_iter()
+                .map(|entry| (entry.address, entry))
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Denied mac list file {:?} not found, starting empty", path);
+                DeniedMacStore::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            store: Arc::new(RwLock::new(macs)),
+            path: Arc::new(path),
+        })
+    }
+
+    /// 変更後の内容をファイルに書き出し、成功した場合のみメモリ上に反映する
+    fn update<F, T>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        F: FnOnce(&mut DeniedMacStore) -> Result<T, RepositoryError>,
+    {
+        if let Ok(mut store) = self.store.write() {
+            let mut next = store.clone();
+            let result = f(&mut next)?;
+            if next != *store {
+                write_denied_macs_atomic(&self.path, &next).map_err(|e| {
+                    error!(
+                        "Failed to write denied mac list file {:?}: {}",
+                        self.path, e
+                    );
+                    RepositoryError::PersistFailed
+                })?;
+                *store = next;
+            }
+            Ok(result)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+impl DeniedMacRepository for DeniedMacRepositoryForFile {
+    fn contains(&self, address: &MacAddr) -> Result<bool, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(is_denied(&store, address))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn get(&self, address: &MacPrefix) -> Result<DeniedMac, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            store.get(address).cloned().ok_or(RepositoryError::NotFound)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<DeniedMac>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(store.values().cloned().collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn add(&self, entry: DeniedMac) -> Result<DeniedMac, RepositoryError> {
+        debug!("MAC address putted to DeniedMacRepository: {:?}", entry);
+        self.update(|store| insert(store, entry))
+    }
+
+    fn remove(&self, address: &MacPrefix) -> Result<(), RepositoryError> {
+        self.update(|store| {
+            store
+                .remove(address)
+                .map(|_| ())
+                .ok_or(RepositoryError::NotFound)
+        })
+    }
+}
+
+/// ファイル中の1件。MACアドレスの文字列も受け付ける
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DeniedMacFileEntry {
+    Address(String),
+    Record(DeniedMacRecord),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeniedMacRecord {
+    mac_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_by: Option<String>,
+}
+
+impl From<&DeniedMac> for DeniedMacRecord {
+    fn from(entry: &DeniedMac) -> Self {
+        Self {
+            mac_address: entry.address.to_string(),
+            label: entry.label.clone(),
+            note: entry.note.clone(),
+            created_at: entry.created_at.map(DateTime::from),
+            created_by: entry.created_by.clone(),
+        }
+    }
+}
+
+/// 拒否するMACアドレスの配列として書かれたJSONを読み込む
+pub fn read_denied_macs(file: File) -> Result<Vec<DeniedMac>, anyhow::Error> {
+    let reader = BufReader::new(file);
+    let entries: Vec<DeniedMacFileEntry> = serde_json::from_reader(reader)?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let record = match entry {
+                DeniedMacFileEntry::Address(mac_address) => DeniedMacRecord {
+                    mac_address,
+                    label: None,
+                    note: None,
+                    created_at: None,
+                    created_by: None,
+                },
+                DeniedMacFileEntry::Record(record) => record,
+            };
+            let address = MacPrefix::from_str(&record.mac_address).map_err(|_| {
+                anyhow::anyhow!("Failed to parse mac address {:?}", record.mac_address)
+            })?;
+            Ok(DeniedMac {
+                address,
+                label: record.label,
+                note: record.note,
+                created_at: record.created_at.map(SystemTime::from),
+                created_by: record.created_by,
+            })
+        })
+        .collect()
+}
+
+fn write_denied_macs_atomic(path: &Path, macs: &DeniedMacStore) -> std::io::Result<()> {
+    let records: Vec<DeniedMacRecord> = macs.values().map(DeniedMacRecord::from).collect();
+    write_json_atomic(path, &records)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use pnet::util::MacAddr;
+
+    use super::{
+        DeniedMac, DeniedMacRepository, DeniedMacRepositoryForFile, DeniedMacRepositoryForMemory,
+    };
+    use crate::repositories::{allowed_mac::MacPrefix, RepositoryError};
+
+    #[test]
+    fn deniedmac_repo_scenario() {
+        let repo = DeniedMacRepositoryForMemory::new();
+        let addr = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let oui = MacPrefix::from_str("00:1b:63:*").unwrap();
+        let created = repo.add(DeniedMac::new(addr)).unwrap();
+        assert!(created.created_at.is_some());
+        assert_eq!(
+            repo.add(DeniedMac::new(addr)),
+            Err(RepositoryError::AlreadyExists)
+        );
+        repo.add(DeniedMac::new(oui)).unwrap();
+        assert!(repo.contains(&addr).unwrap());
+        assert!(repo
+            .contains(&MacAddr::new(0, 0x1b, 0x63, 1, 2, 3))
+            .unwrap());
+        assert!(!repo.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2)).unwrap());
+        assert_eq!(repo.getall().unwrap().len(), 2);
+        assert_eq!(repo.get(&oui).unwrap().address, oui);
+        repo.remove(&oui).unwrap();
+        assert_eq!(repo.remove(&oui), Err(RepositoryError::NotFound));
+        assert!(!repo
+            .contains(&MacAddr::new(0, 0x1b, 0x63, 1, 2, 3))
+            .unwrap());
+    }
+
+    #[test]
+    fn deniedmac_file_repo_scenario() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("denied_macs.json");
+        std::fs::write(
+            &path,
+            r#"["02:00:00:00:0f:01", { "mac_address": "00:1b:63:*", "label": "camera" }]"#,
+        )
+        .unwrap();
+        let repo = DeniedMacRepositoryForFile::open(path.clone()).unwrap();
+        assert!(repo.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)).unwrap());
+        assert!(repo
+            .contains(&MacAddr::new(0, 0x1b, 0x63, 1, 2, 3))
+            .unwrap());
+        repo.add(DeniedMac {
+            label: Some("phone".to_string()),
+            ..DeniedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 2))
+        })
+        .unwrap();
+        repo.remove(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into())
+            .unwrap();
+        // 再読み込みしても変更が残る
+        let repo = DeniedMacRepositoryForFile::open(path).unwrap();
+        let entries = repo.getall().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(!repo.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)).unwrap());
+        assert_eq!(
+            repo.get(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into())
+                .unwrap()
+                .label
+                .as_deref(),
+            Some("phone")
+        );
+    }
+}
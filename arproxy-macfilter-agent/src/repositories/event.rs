@@ -0,0 +1,144 @@
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    net::Ipv4Addr,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use utoipa::ToSchema;
+
+use super::RepositoryError;
+
+/// 保持するイベントの最大件数。超えた場合は古いものから削除する
+pub const EVENT_LOG_CAPACITY: usize = 1000;
+
+/// 許可されたMACアドレスについて検知した事象の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// `allowed_ips` に含まれないIPアドレスを使用した
+    UnexpectedIp,
+    /// 短時間のうちに複数のIPアドレスで観測された
+    DuplicateMac,
+    /// 複数のIPアドレスの間で頻繁に切り替わった
+    Flapping,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// 記録した順に振られる番号
+    pub id: u64,
+    pub time: SystemTime,
+    pub kind: EventKind,
+    pub mac: MacAddr,
+    /// 観測されたIPアドレス
+    pub ips: Vec<Ipv4Addr>,
+    /// 自動で許可を取り消したか
+    pub revoked: bool,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, mac: MacAddr, ips: Vec<Ipv4Addr>) -> Self {
+        Self {
+            id: 0,
+            time: SystemTime::now(),
+            kind,
+            mac,
+            ips,
+            revoked: false,
+        }
+    }
+}
+
+pub trait EventRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// イベントを記録する。`id` はリポジトリが振り直す
+    fn push(&self, event: Event) -> Result<Event, RepositoryError>;
+    /// 記録した順に全てのイベントを取得する
+    fn getall(&self) -> Result<Vec<Event>, RepositoryError>;
+}
+
+#[derive(Debug, Default)]
+struct EventStore {
+    events: VecDeque<Event>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventRepositoryForMemory {
+    store: Arc<RwLock<EventStore>>,
+    capacity: usize,
+}
+
+impl EventRepositoryForMemory {
+    pub fn new() -> Self {
+        Self::with_capacity(EVENT_LOG_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            store: Arc::default(),
+            capacity,
+        }
+    }
+}
+
+impl EventRepository for EventRepositoryForMemory {
+    fn push(&self, mut event: Event) -> Result<Event, RepositoryError> {
+        if let Ok(mut store) = self.store.write() {
+            store.next_id += 1;
+            event.id = store.next_id;
+            while store.events.len() >= self.capacity.max(1) {
+                store.events.pop_front();
+            }
+            store.events.push_back(event.clone());
+            Ok(event)
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<Event>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(store.events.iter().cloned().collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use pnet::util::MacAddr;
+
+    use super::{Event, EventKind, EventRepository, EventRepositoryForMemory};
+
+    #[test]
+    fn event_repo_scenario() {
+        let repo = EventRepositoryForMemory::with_capacity(2);
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let ips = vec![
+            Ipv4Addr::new(192, 168, 0, 10),
+            Ipv4Addr::new(192, 168, 0, 11),
+        ];
+        let first = repo
+            .push(Event::new(EventKind::DuplicateMac, mac, ips.clone()))
+            .unwrap();
+        assert_eq!(first.id, 1);
+        repo.push(Event::new(EventKind::Flapping, mac, ips.clone()))
+            .unwrap();
+        // 上限を超えると古いものから削除する
+        let third = repo
+            .push(Event::new(EventKind::UnexpectedIp, mac, vec![ips[0]]))
+            .unwrap();
+        assert_eq!(third.id, 3);
+        let events = repo.getall().unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(events[1].kind, EventKind::UnexpectedIp);
+    }
+}
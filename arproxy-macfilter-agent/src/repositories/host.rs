@@ -0,0 +1,226 @@
+use pnet::util::MacAddr;
+use std::{
+    collections::HashMap,
+    net::Ipv4Addr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, error, trace};
+
+use super::RepositoryError;
+
+/// インターフェース上で観測されたMACアドレスとIPアドレスの組
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    pub mac: MacAddr,
+    pub ip: Ipv4Addr,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// 最後に観測した時点で許可されていたか
+    pub allowed: bool,
+}
+
+/// 保持するHostの最大件数。超えた場合は最後の観測が古いものからまとめて削除する
+pub const HOST_CAPACITY: usize = 16384;
+/// この期間観測されなかったHostは削除する
+pub const HOST_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// 観測されなくなったHostを削除する間隔
+pub const HOST_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub trait HostRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    /// Hostを挿入またはlast_seen/allowedを更新する
+    fn put(&self, mac: MacAddr, ip: Ipv4Addr, allowed: bool) -> Result<(), RepositoryError>;
+    fn getall(&self) -> Result<Vec<Host>, RepositoryError>;
+    /// IPアドレスを最後に使用していたMACアドレスを取得する
+    fn lookup_mac(&self, ip: &Ipv4Addr) -> Result<Option<MacAddr>, RepositoryError>;
+    /// `now` の時点で保持期間を過ぎたHostを削除し、削除した件数を返す
+    fn prune(&self, now: SystemTime) -> Result<usize, RepositoryError>;
+}
+
+#[derive(Debug, Default)]
+struct HostStore {
+    hosts: HashMap<(MacAddr, Ipv4Addr), Host>,
+    /// IPアドレスごとに最後に使用していたMACアドレスとその時刻
+    latest: HashMap<Ipv4Addr, (MacAddr, SystemTime)>,
+}
+
+impl HostStore {
+    /// `cutoff` 以前に最後に観測されたHostを削除する
+    ///
+    /// あるIPアドレスの最新のHostが削除される場合、同じIPアドレスのHostは全て削除されるため `latest` も同じ条件で削除できる
+    fn remove_until(&mut self, cutoff: SystemTime) -> usize {
+        let before = self.hosts.len();
+        self.hosts.retain(|_, host| host.last_seen > cutoff);
+        self.latest.retain(|_, (_, last_seen)| *last_seen > cutoff);
+        before - self.hosts.len()
+    }
+
+    /// 最後の観測が古いものから `count` 件以上を削除する
+    fn evict_oldest(&mut self, count: usize) -> usize {
+        let mut times: Vec<SystemTime> = self.hosts.values().map(|host| host.last_seen).collect();
+        if times.is_empty() {
+            return 0;
+        }
+        let index = count.clamp(1, times.len()) - 1;
+        let (_, cutoff, _) = times.select_nth_unstable(index);
+        let cutoff = *cutoff;
+        self.remove_until(cutoff)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HostRepositoryForMemory {
+    store: Arc<RwLock<HostStore>>,
+    capacity: usize,
+    lifetime: Duration,
+}
+
+impl HostRepositoryForMemory {
+    pub fn new() -> Self {
+        Self::with_limits(HOST_CAPACITY, HOST_LIFETIME)
+    }
+
+    pub fn with_limits(capacity: usize, lifetime: Duration) -> Self {
+        Self {
+            store: Arc::default(),
+            capacity: capacity.max(1),
+            lifetime,
+        }
+    }
+}
+
+impl HostRepository for HostRepositoryForMemory {
+    fn put(&self, mac: MacAddr, ip: Ipv4Addr, allowed: bool) -> Result<(), RepositoryError> {
+        let now = SystemTime::now();
+        if let Ok(mut store) = self.store.write() {
+            if let Some(host) = store.hosts.get_mut(&(mac, ip)) {
+                host.last_seen = now;
+                host.allowed = allowed;
+            } else {
+                if store.hosts.len() >= self.capacity {
+                    // 1件ずつ探すと上限付近で毎回全件を走査するため、まとめて削除する
+                    let evicted = store.evict_oldest(self.capacity.div_ceil(8));
+                    debug!("Host inventory is full, evicted {} hosts", evicted);
+                }
+                trace!("New host found: {} is at {}", ip, mac);
+                store.hosts.insert(
+                    (mac, ip),
+                    Host {
+                        mac,
+                        ip,
+                        first_seen: now,
+                        last_seen: now,
+                        allowed,
+                    },
+                );
+            }
+            store.latest.insert(ip, (mac, now));
+            Ok(())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn getall(&self) -> Result<Vec<Host>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(store.hosts.values().cloned().collect())
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn lookup_mac(&self, ip: &Ipv4Addr) -> Result<Option<MacAddr>, RepositoryError> {
+        if let Ok(store) = self.store.read() {
+            Ok(store.latest.get(ip).map(|(mac, _)| *mac))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+
+    fn prune(&self, now: SystemTime) -> Result<usize, RepositoryError> {
+        let Some(cutoff) = now.checked_sub(self.lifetime) else {
+            return Ok(0);
+        };
+        if let Ok(mut store) = self.store.write() {
+            Ok(store.remove_until(cutoff))
+        } else {
+            Err(RepositoryError::SyncFailed)
+        }
+    }
+}
+
+/// 保持期間を過ぎたHostを定期的に削除する
+pub async fn prune_loop<H: HostRepository>(host_repo: H) {
+    let mut interval = tokio::time::interval(HOST_PRUNE_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match host_repo.prune(SystemTime::now()) {
+            Ok(0) => {}
+            Ok(pruned) => debug!("Pruned {} hosts from the inventory", pruned),
+            Err(e) => error!("Failed to prune hosts: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::Ipv4Addr,
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use pnet::util::MacAddr;
+
+    use super::{HostRepository, HostRepositoryForMemory};
+
+    #[test]
+    fn host_repo_scenario() {
+        let repo = HostRepositoryForMemory::new();
+        let ip = Ipv4Addr::new(192, 168, 0, 1);
+        let mac1 = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let mac2 = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        // put scenario
+        repo.put(mac1, ip, false).expect("SyncErr");
+        let first_seen = repo.getall().unwrap()[0].first_seen;
+        repo.put(mac1, ip, true).expect("SyncErr");
+        let hosts = repo.getall().unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].first_seen, first_seen);
+        assert!(hosts[0].last_seen >= first_seen);
+        assert!(hosts[0].allowed);
+        assert_eq!(repo.lookup_mac(&ip).unwrap(), Some(mac1));
+        // 同じIPアドレスを別のMACアドレスが使用した場合は別のHostとして記録する
+        thread::sleep(Duration::from_millis(10));
+        repo.put(mac2, ip, false).expect("SyncErr");
+        assert_eq!(repo.getall().unwrap().len(), 2);
+        assert_eq!(repo.lookup_mac(&ip).unwrap(), Some(mac2));
+        assert_eq!(
+            repo.lookup_mac(&Ipv4Addr::new(192, 168, 0, 2)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn host_repo_limits() {
+        let repo = HostRepositoryForMemory::with_limits(4, Duration::from_secs(60));
+        let mac = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let ips: Vec<Ipv4Addr> = (1..=5).map(|i| Ipv4Addr::new(192, 168, 0, i)).collect();
+        for ip in ips[0..4].iter() {
+            repo.put(mac, *ip, false).expect("SyncErr");
+            thread::sleep(Duration::from_millis(2));
+        }
+        // 上限を超えると最後の観測が古いものから削除する
+        repo.put(mac, ips[4], false).expect("SyncErr");
+        assert_eq!(repo.getall().unwrap().len(), 4);
+        assert_eq!(repo.lookup_mac(&ips[0]).unwrap(), None);
+        assert_eq!(repo.lookup_mac(&ips[4]).unwrap(), Some(mac));
+        // 保持期間内のものは削除しない
+        assert_eq!(repo.prune(SystemTime::now()).unwrap(), 0);
+        let later = SystemTime::now() + Duration::from_secs(61);
+        assert_eq!(repo.prune(later).unwrap(), 4);
+        assert_eq!(repo.getall().unwrap().len(), 0);
+        assert_eq!(repo.lookup_mac(&ips[4]).unwrap(), None);
+    }
+}
@@ -1,2 +1,5 @@
 pub(crate) mod api;
+pub(crate) mod auth;
 pub(crate) mod route;
+pub(crate) mod server;
+pub(crate) mod tls;
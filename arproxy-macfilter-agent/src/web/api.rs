@@ -1,3 +1,5 @@
+pub(crate) mod error;
 mod handlers;
+pub(crate) mod openapi;
 pub(super) mod route;
 mod schema;
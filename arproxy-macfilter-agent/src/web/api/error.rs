@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::repositories::RepositoryError;
+
+/// RFC 7807 のエラーレスポンスのContent-Type
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// APIのエラー。RFC 7807 の problem+json 形式でレスポンスを返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    status: StatusCode,
+    detail: Option<String>,
+    errors: BTreeMap<String, Vec<FieldError>>,
+}
+
+/// 検証に失敗したフィールドごとのエラー
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FieldError {
+    pub code: String,
+    pub message: String,
+}
+
+/// problem+json のレスポンスボディ
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<String, Vec<FieldError>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: Some(detail.into()),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, detail)
+    }
+
+    /// 内部エラー。原因はログにのみ記録し、クライアントには返さない
+    pub fn internal(cause: impl std::fmt::Display) -> Self {
+        error!("Internal server error: {}", cause);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string(),
+            status: self.status.as_u16(),
+            detail: self.detail,
+            errors: self.errors,
+        };
+        let mut res = (self.status, Json(body)).into_response();
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        res
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::NotFound => Self::not_found(e.to_string()),
+            RepositoryError::AlreadyExists => Self::new(StatusCode::CONFLICT, e.to_string()),
+            RepositoryError::InvalidValue(_) => Self::bad_request(e.to_string()),
+            RepositoryError::SyncFailed | RepositoryError::PersistFailed => Self::internal(e),
+        }
+    }
+}
+
+/// JSONの構文やデータ型の誤りはいずれも400とする
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::bad_request(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = BTreeMap::new();
+        collect_field_errors(&mut fields, None, &errors);
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            detail: Some("Validation failed".to_string()),
+            errors: fields,
+        }
+    }
+}
+
+/// 入れ子になった検証エラーを `arp_proxy.arp_reply_interval` のようなドット区切りの名前で平坦化する
+fn collect_field_errors(
+    fields: &mut BTreeMap<String, Vec<FieldError>>,
+    prefix: Option<&str>,
+    errors: &ValidationErrors,
+) {
+    for (field, kind) in errors.errors() {
+        let name = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, field),
+            None => field.to_string(),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                fields
+                    .entry(name)
+                    .or_default()
+                    .extend(errors.iter().map(|e| {
+                        FieldError {
+                            code: e.code.to_string(),
+                            message: e
+                                .message
+                                .as_ref()
+                                .map_or_else(|| e.code.to_string(), |m| m.to_string()),
+                        }
+                    }));
+            }
+            ValidationErrorsKind::Struct(errors) => {
+                collect_field_errors(fields, Some(&name), errors);
+            }
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(fields, Some(&format!("{}[{}]", name, index)), errors);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        http::{header, StatusCode},
+        response::IntoResponse,
+    };
+    use http_body_util::BodyExt;
+    use validator::Validate;
+
+    use super::{ApiError, ProblemDetails, PROBLEM_JSON};
+    use crate::{repositories::RepositoryError, web::api::schema::AllowedMacSchema};
+
+    async fn into_problem(e: ApiError) -> (StatusCode, ProblemDetails) {
+        let res = e.into_response();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn convert_repository_error() {
+        let cases = [
+            (RepositoryError::NotFound, StatusCode::NOT_FOUND),
+            (RepositoryError::AlreadyExists, StatusCode::CONFLICT),
+            (
+                RepositoryError::InvalidValue("bad".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                RepositoryError::SyncFailed,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                RepositoryError::PersistFailed,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (e, expected) in cases {
+            let (status, problem) = into_problem(ApiError::from(e)).await;
+            assert_eq!(status, expected);
+            assert_eq!(problem.status, expected.as_u16());
+            assert_eq!(problem.title, expected.canonical_reason().unwrap());
+            assert_eq!(problem.problem_type, "about:blank");
+        }
+    }
+
+    #[tokio::test]
+    async fn convert_validation_errors() {
+        let schema = AllowedMacSchema {
+            mac_address: "hello, world".to_string(),
+            ..Default::default()
+        };
+        let e = ApiError::from(schema.validate().unwrap_err());
+        let (status, problem) = into_problem(e).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let errors = &problem.errors["mac_address"];
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, "invalid_mac_address");
+        assert_eq!(errors[0].message, "Invalid MAC address or prefix");
+    }
+}
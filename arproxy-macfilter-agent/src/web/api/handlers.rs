@@ -1,18 +1,38 @@
-use std::{str::FromStr, sync::Arc};
+use std::{collections::BTreeMap, net::Ipv4Addr, str::FromStr, sync::Arc};
 
-use crate::repositories::allowed_mac::AllowedMacRepository;
+use crate::{
+    config::ProxyMode,
+    networks::healer::{HealRequest, HealRequester},
+    repositories::{
+        allowed_mac::{AllowedMacRepository, BulkOutcome, MacPrefix},
+        arplog::{ArpLog, ArpLogRepository},
+        config::ConfigRepository,
+        denied_mac::DeniedMacRepository,
+        event::EventRepository,
+        host::HostRepository,
+        RepositoryError,
+    },
+    web::auth::Principal,
+};
 use axum::{
-    extract::{FromRequest, Request},
-    http::StatusCode,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Path, Query, Request},
+    http::{header, request::Parts, StatusCode},
     response::{IntoResponse, Result},
     Extension, Json,
 };
 use pnet::util::MacAddr;
 use serde::de::DeserializeOwned;
-use tracing::debug;
+use tracing::{debug, error, info};
 use validator::Validate;
 
-use super::schema::{AllowedMacDeleteSchema, AllowedMacPostResponseSchema, AllowedMacPostSchema};
+use super::error::{ApiError, ProblemDetails};
+use super::schema::{
+    AllowedMacBulkResponseSchema, AllowedMacBulkSchema, AllowedMacDeleteSchema,
+    AllowedMacMetadataSchema, AllowedMacPostResponseSchema, AllowedMacPostSchema, AllowedMacSchema,
+    ArpLogSchema, ArpLogTargetSchema, ConfigPatchSchema, ConfigSchema, DeniedMacMetadataSchema,
+    DeniedMacSchema, EventQuerySchema, EventSchema, HostQuerySchema, HostSchema,
+};
 
 #[derive(Debug)]
 pub struct ValidatedJson<T>(T);
@@ -23,52 +43,659 @@ where
     T: DeserializeOwned + Validate,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req, state)
-            .await
-            .map_err(|_| (StatusCode::BAD_REQUEST, "Json parse error.".to_string()))?;
-        value
-            .validate()
-            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid schema".to_string()))?;
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate()?;
         Ok(ValidatedJson(value))
     }
 }
 
+/// 失敗時に `ApiError` を返す `Path`
+#[derive(Debug)]
+pub struct ApiPath<T>(T);
+
+#[axum::async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state).await?;
+        Ok(ApiPath(value))
+    }
+}
+
+/// 失敗時に `ApiError` を返す `Query`
+#[derive(Debug)]
+pub struct ApiQuery<T>(T);
+
+#[axum::async_trait]
+impl<T, S> FromRequestParts<S> for ApiQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await?;
+        Ok(ApiQuery(value))
+    }
+}
+
+fn parse_mac(text: &str) -> Result<MacAddr, ApiError> {
+    MacAddr::from_str(text)
+        .map_err(|_| ApiError::bad_request(format!("Invalid MAC address: {:?}", text)))
+}
+
+/// 許可リストのMACアドレス、またはプレフィックス
+fn parse_prefix(text: &str) -> Result<MacPrefix, ApiError> {
+    MacPrefix::from_str(text)
+        .map_err(|_| ApiError::bad_request(format!("Invalid MAC address or prefix: {:?}", text)))
+}
+
+/// 監査ログに記録する操作者の名前。認証が無効な場合は "anonymous"
+fn requester(principal: &Option<Extension<Principal>>) -> &str {
+    principal
+        .as_ref()
+        .map_or("anonymous", |Extension(principal)| principal.name.as_str())
+}
+
+/// 許可リストのエントリに記録する作成者の名前。認証が無効な場合はNone
+fn creator(principal: &Option<Extension<Principal>>) -> Option<String> {
+    principal
+        .as_ref()
+        .map(|Extension(principal)| principal.name.clone())
+}
+
+/// 本文が空の場合は `T::default()` とする `ValidatedJson`
+///
+/// Content-Typeのない空でない本文は415で拒否する
+#[derive(Debug)]
+pub struct ValidatedJsonOrDefault<T>(T);
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for ValidatedJsonOrDefault<T>
+where
+    T: DeserializeOwned + Validate + Default,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if req.headers().contains_key(header::CONTENT_TYPE) {
+            let ValidatedJson(value) = ValidatedJson::<T>::from_request(req, state).await?;
+            return Ok(ValidatedJsonOrDefault(value));
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+        if !body.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`",
+            ));
+        }
+        Ok(ValidatedJsonOrDefault(T::default()))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/allowed-mac/add",
+    tag = "allowed-mac",
+    request_body = AllowedMacSchema,
+    responses(
+        (status = 201, description = "許可した", body = AllowedMacSchema),
+        (status = 400, description = "不正な入力", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
 pub async fn add_allowedmac<M: AllowedMacRepository>(
     Extension(allowedmac_repo): Extension<Arc<M>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
     ValidatedJson(payload): ValidatedJson<AllowedMacPostSchema>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<impl IntoResponse, ApiError> {
     debug!("Adding MAC addres: {:?}", payload);
-    let addr = MacAddr::from_str(&payload.mac_address).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let result = allowedmac_repo.add(addr);
+    let addr = parse_prefix(&payload.mac_address)?;
+    let result = allowedmac_repo.add(payload.to_entry(addr, creator(&principal)));
     debug!("Adding MAC addres: {:?}", result);
-    let created_addr = result.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 旧APIでは既に登録済みの場合も成功として扱う
+    let created = match result {
+        Ok(created) => created,
+        Err(RepositoryError::AlreadyExists) => allowedmac_repo.get(&addr)?,
+        Err(e) => return Err(e.into()),
+    };
+    info!("{} allowed {}", requester(&principal), addr);
+    // 許可したMACアドレスへの偽装を直ちに打ち消す
+    if let Err(e) = healer.request(HealRequest::from(addr)) {
+        error!("Failed to request healing: {}", e);
+    }
     Ok((
         StatusCode::CREATED,
-        Json(AllowedMacPostResponseSchema {
-            mac_address: created_addr.to_string(),
-        }),
+        Json(AllowedMacPostResponseSchema::from(created)),
     ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/allowed-mac/all",
+    tag = "allowed-mac",
+    responses((status = 200, description = "許可されたMACアドレスの一覧", body = Vec<String>)),
+)]
 pub async fn all_allowedmac<M: AllowedMacRepository>(
     Extension(allowedmac_repo): Extension<Arc<M>>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<impl IntoResponse, ApiError> {
     let result = allowedmac_repo.getall();
-    let addrs = result.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let addrs = result?;
     let addrs_str: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
     Ok((StatusCode::OK, Json(addrs_str)))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/allowed-mac/delete",
+    tag = "allowed-mac",
+    request_body = AllowedMacSchema,
+    responses(
+        (status = 204, description = "許可を取り消した"),
+        (status = 400, description = "不正な入力", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
 pub async fn delete_allowedmac<M: AllowedMacRepository>(
     Extension(allowedmac_repo): Extension<Arc<M>>,
+    principal: Option<Extension<Principal>>,
     ValidatedJson(payload): ValidatedJson<AllowedMacDeleteSchema>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let addr = MacAddr::from_str(&payload.mac_address).map_err(|_| StatusCode::BAD_REQUEST)?;
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&payload.mac_address)?;
     let result = allowedmac_repo.remove(&addr);
     debug!("Deleting allowed mac... {:?}", result);
-    result.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // 旧APIでは登録されていない場合も成功として扱う
+    match result {
+        Ok(()) | Err(RepositoryError::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+    info!("{} disallowed {}", requester(&principal), addr);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/allowed-macs",
+    tag = "allowed-macs",
+    responses((status = 200, description = "許可されたMACアドレスの一覧", body = Vec<AllowedMacSchema>)),
+)]
+pub async fn list_allowedmacs<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut entries = allowedmac_repo.getall_entries()?;
+    entries.sort_by_key(|entry| entry.address);
+    let entries: Vec<AllowedMacSchema> = entries.into_iter().map(AllowedMacSchema::from).collect();
+    Ok((StatusCode::OK, Json(entries)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/allowed-macs/{mac_address}",
+    tag = "allowed-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    responses(
+        (status = 200, description = "許可されている", body = AllowedMacSchema),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "許可されていない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn get_allowedmac<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+    ApiPath(mac_address): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    let entry = allowedmac_repo.get(&addr)?;
+    Ok((StatusCode::OK, Json(AllowedMacSchema::from(entry))))
+}
+
+/// MACアドレスを許可する。既に許可されている場合は409を返す
+#[utoipa::path(
+    put,
+    path = "/api/v1/allowed-macs/{mac_address}",
+    tag = "allowed-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    request_body(content = Option<AllowedMacMetadataSchema>, description = "付帯情報 (省略可)"),
+    responses(
+        (status = 201, description = "許可した", body = AllowedMacSchema),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "既に許可されている", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn put_allowedmac<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+    ValidatedJsonOrDefault(metadata): ValidatedJsonOrDefault<AllowedMacMetadataSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    let entry = AllowedMacSchema {
+        mac_address,
+        label: metadata.label,
+        owner: metadata.owner,
+        note: metadata.note,
+        expires_at: metadata.expires_at.flatten(),
+        ttl: metadata.ttl,
+        schedules: metadata.schedules.unwrap_or_default(),
+        allowed_ips: metadata.allowed_ips.unwrap_or_default(),
+        ..Default::default()
+    }
+    .to_entry(addr, creator(&principal));
+    let created = allowedmac_repo.add(entry)?;
+    info!("{} allowed {}", requester(&principal), addr);
+    // 許可したMACアドレスへの偽装を直ちに打ち消す
+    if let Err(e) = healer.request(HealRequest::from(addr)) {
+        error!("Failed to request healing: {}", e);
+    }
+    Ok((StatusCode::CREATED, Json(AllowedMacSchema::from(created))))
+}
+
+/// 許可されたMACアドレスの付帯情報を変更する
+#[utoipa::path(
+    patch,
+    path = "/api/v1/allowed-macs/{mac_address}",
+    tag = "allowed-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    request_body = AllowedMacMetadataSchema,
+    responses(
+        (status = 200, description = "変更後のエントリ", body = AllowedMacSchema),
+        (status = 400, description = "不正な入力", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "許可されていない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn patch_allowedmac<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+    ValidatedJson(metadata): ValidatedJson<AllowedMacMetadataSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    let entry = allowedmac_repo.modify(&addr, metadata.into())?;
+    info!("{} updated metadata of {}", requester(&principal), addr);
+    Ok((StatusCode::OK, Json(AllowedMacSchema::from(entry))))
+}
+
+/// MACアドレスの許可を取り消す。許可されていない場合は404を返す
+#[utoipa::path(
+    delete,
+    path = "/api/v1/allowed-macs/{mac_address}",
+    tag = "allowed-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    responses(
+        (status = 204, description = "許可を取り消した"),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "許可されていない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn remove_allowedmac<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    allowedmac_repo.remove(&addr)?;
+    info!("{} disallowed {}", requester(&principal), addr);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 複数のMACアドレスをまとめて追加・削除・置換する
+#[utoipa::path(
+    post,
+    path = "/api/v1/allowed-macs/bulk",
+    tag = "allowed-macs",
+    request_body = AllowedMacBulkSchema,
+    responses(
+        (status = 200, description = "各MACアドレスの結果", body = AllowedMacBulkResponseSchema),
+        (status = 400, description = "不正な入力。何も変更しない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn bulk_allowedmacs<M: AllowedMacRepository>(
+    Extension(allowedmac_repo): Extension<Arc<M>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
+    ValidatedJson(payload): ValidatedJson<AllowedMacBulkSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let created_by = creator(&principal);
+    let entries = payload
+        .entries
+        .iter()
+        .map(|entry| Ok(entry.to_entry(parse_prefix(&entry.mac_address)?, created_by.clone())))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    let result = allowedmac_repo.bulk(payload.operation.into(), &entries)?;
+    let added: Vec<MacPrefix> = result
+        .outcomes
+        .iter()
+        .filter(|(_, outcome)| *outcome == BulkOutcome::Added)
+        .map(|(addr, _)| *addr)
+        .collect();
+    let removed = result
+        .outcomes
+        .iter()
+        .filter(|(_, outcome)| *outcome == BulkOutcome::Removed)
+        .count()
+        + result.removed.len();
+    info!(
+        "{} applied bulk {:?}: {} allowed, {} disallowed",
+        requester(&principal),
+        payload.operation,
+        added.len(),
+        removed
+    );
+    // 許可したMACアドレスへの偽装を直ちに打ち消す
+    for addr in added {
+        if let Err(e) = healer.request(HealRequest::from(addr)) {
+            error!("Failed to request healing: {}", e);
+        }
+    }
+    Ok((
+        StatusCode::OK,
+        Json(AllowedMacBulkResponseSchema::new(payload.operation, result)),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/denied-macs",
+    tag = "denied-macs",
+    responses((status = 200, description = "拒否リストのMACアドレスの一覧", body = Vec<DeniedMacSchema>)),
+)]
+pub async fn list_deniedmacs<D: DeniedMacRepository>(
+    Extension(deniedmac_repo): Extension<Arc<D>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let entries: Vec<DeniedMacSchema> = deniedmac_repo
+        .getall()?
+        .into_iter()
+        .map(DeniedMacSchema::from)
+        .collect();
+    Ok((StatusCode::OK, Json(entries)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/denied-macs/{mac_address}",
+    tag = "denied-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    responses(
+        (status = 200, description = "拒否リストに含まれる", body = DeniedMacSchema),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "拒否リストに含まれない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn get_deniedmac<D: DeniedMacRepository>(
+    Extension(deniedmac_repo): Extension<Arc<D>>,
+    ApiPath(mac_address): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    let entry = deniedmac_repo.get(&addr)?;
+    Ok((StatusCode::OK, Json(DeniedMacSchema::from(entry))))
+}
+
+/// MACアドレスを拒否リストに追加する。既に含まれている場合は409を返す
+///
+/// 拒否リストモードでは、次のARPリクエストから偽装の対象となる
+#[utoipa::path(
+    put,
+    path = "/api/v1/denied-macs/{mac_address}",
+    tag = "denied-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    request_body(content = Option<DeniedMacMetadataSchema>, description = "付帯情報 (省略可)"),
+    responses(
+        (status = 201, description = "追加した", body = DeniedMacSchema),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "既に含まれている", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn put_deniedmac<D: DeniedMacRepository>(
+    Extension(deniedmac_repo): Extension<Arc<D>>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+    ValidatedJsonOrDefault(metadata): ValidatedJsonOrDefault<DeniedMacMetadataSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    let created = deniedmac_repo.add(metadata.to_entry(addr, creator(&principal)))?;
+    info!("{} denied {}", requester(&principal), addr);
+    Ok((StatusCode::CREATED, Json(DeniedMacSchema::from(created))))
+}
+
+/// MACアドレスを拒否リストから削除する。含まれていない場合は404を返す
+///
+/// 拒否リストモードでは、削除したMACアドレスへの偽装を直ちに打ち消す
+#[utoipa::path(
+    delete,
+    path = "/api/v1/denied-macs/{mac_address}",
+    tag = "denied-macs",
+    params(("mac_address" = String, Path, description = "MACアドレス、またはプレフィックス (`00:1b:63:*`、`02:00:00:00:00:00%2F40`)", example = "02:00:00:00:00:01")),
+    responses(
+        (status = 204, description = "削除した"),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "拒否リストに含まれない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn remove_deniedmac<C: ConfigRepository, D: DeniedMacRepository>(
+    Extension(config_repo): Extension<Arc<C>>,
+    Extension(deniedmac_repo): Extension<Arc<D>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_prefix(&mac_address)?;
+    deniedmac_repo.remove(&addr)?;
+    info!("{} undenied {}", requester(&principal), addr);
+    // 許可リストモードでは拒否リストから削除しても偽装の対象であり続けるため修復しない
+    if config_repo.get_config().arp_proxy.mode == ProxyMode::Denylist {
+        if let Err(e) = healer.request(HealRequest::from(addr)) {
+            error!("Failed to request healing: {}", e);
+        }
+    }
     Ok(StatusCode::NO_CONTENT)
 }
+
+#[utoipa::path(
+    get,
+    path = "/api/hosts",
+    tag = "hosts",
+    params(HostQuerySchema),
+    responses(
+        (status = 200, description = "観測したホストの一覧", body = Vec<HostSchema>),
+        (status = 400, description = "不正な絞り込み条件", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn all_hosts<H: HostRepository>(
+    Extension(host_repo): Extension<Arc<H>>,
+    ApiQuery(query): ApiQuery<HostQuerySchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mac = match query.mac_address {
+        Some(mac) => Some(parse_mac(&mac)?),
+        None => None,
+    };
+    let mut hosts = host_repo
+        .getall()?
+        .into_iter()
+        .filter(|host| mac.is_none_or(|mac| host.mac == mac))
+        .filter(|host| query.ip_address.is_none_or(|ip| host.ip == ip))
+        .collect::<Vec<_>>();
+    hosts.sort_by_key(|host| (host.ip, host.mac));
+    let hosts: Vec<HostSchema> = hosts.into_iter().map(HostSchema::from).collect();
+    Ok((StatusCode::OK, Json(hosts)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "events",
+    params(EventQuerySchema),
+    responses(
+        (status = 200, description = "許可されたMACアドレスについて検知した事象の一覧 (古い順)", body = Vec<EventSchema>),
+        (status = 400, description = "不正な絞り込み条件", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn all_events<E: EventRepository>(
+    Extension(event_repo): Extension<Arc<E>>,
+    ApiQuery(query): ApiQuery<EventQuerySchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mac = match query.mac_address {
+        Some(mac) => Some(parse_mac(&mac)?),
+        None => None,
+    };
+    let events: Vec<EventSchema> = event_repo
+        .getall()?
+        .into_iter()
+        .filter(|event| mac.is_none_or(|mac| event.mac == mac))
+        .filter(|event| query.kind.is_none_or(|kind| event.kind == kind))
+        .map(EventSchema::from)
+        .collect();
+    Ok((StatusCode::OK, Json(events)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/arplog",
+    tag = "arplog",
+    responses((status = 200, description = "偽装中のホストの一覧", body = Vec<ArpLogSchema>)),
+)]
+pub async fn all_arplog<A: ArpLogRepository>(
+    Extension(arplog_repo): Extension<Arc<A>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let arplogs = arplog_repo.getall_without_autoclear()?;
+    let mut senders: BTreeMap<MacAddr, Vec<ArpLog>> = BTreeMap::new();
+    for arplog in arplogs {
+        senders.entry(arplog.sender_mac).or_default().push(arplog);
+    }
+    let senders: Vec<ArpLogSchema> = senders
+        .into_iter()
+        .map(|(sender_mac, mut arplogs)| {
+            arplogs.sort_by_key(|arplog| arplog.target_ip);
+            ArpLogSchema {
+                sender_mac: sender_mac.to_string(),
+                sender_ip: arplogs[0].sender_ip,
+                targets: arplogs.iter().map(ArpLogTargetSchema::from).collect(),
+            }
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(senders)))
+}
+
+/// 送信元への偽装を止める。ArpLogの削除と修復は修復タスクが行う
+#[utoipa::path(
+    delete,
+    path = "/api/arplog/{mac_address}",
+    tag = "arplog",
+    params(("mac_address" = String, Path, description = "MACアドレス", example = "02:00:00:00:00:01")),
+    responses(
+        (status = 202, description = "偽装の停止を受け付けた"),
+        (status = 400, description = "不正なMACアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "偽装していない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn delete_arplog_sender<A: ArpLogRepository>(
+    Extension(arplog_repo): Extension<Arc<A>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
+    ApiPath(mac_address): ApiPath<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_mac(&mac_address)?;
+    let found = arplog_repo
+        .getall_without_autoclear()?
+        .iter()
+        .any(|arplog| arplog.sender_mac == addr);
+    if !found {
+        return Err(ApiError::not_found(format!("{} is not spoofed", addr)));
+    }
+    healer
+        .request(HealRequest::Sender(addr))
+        .map_err(|e| ApiError::internal(format!("Failed to request healing: {}", e)))?;
+    info!("{} stopped spoofing for {}", requester(&principal), addr);
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// 送信元から目標IPアドレスへの偽装を止める。ArpLogの削除と修復は修復タスクが行う
+#[utoipa::path(
+    delete,
+    path = "/api/arplog/{mac_address}/{target_ip}",
+    tag = "arplog",
+    params(
+        ("mac_address" = String, Path, description = "MACアドレス", example = "02:00:00:00:00:01"),
+        ("target_ip" = String, Path, description = "目標IPアドレス", example = "192.168.0.1"),
+    ),
+    responses(
+        (status = 202, description = "偽装の停止を受け付けた"),
+        (status = 400, description = "不正なMACアドレス・IPアドレス", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "偽装していない", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn delete_arplog_target<A: ArpLogRepository>(
+    Extension(arplog_repo): Extension<Arc<A>>,
+    Extension(healer): Extension<HealRequester>,
+    principal: Option<Extension<Principal>>,
+    ApiPath((mac_address, target_ip)): ApiPath<(String, Ipv4Addr)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let addr = parse_mac(&mac_address)?;
+    let found = arplog_repo
+        .getall_without_autoclear()?
+        .iter()
+        .any(|arplog| arplog.sender_mac == addr && arplog.target_ip == target_ip);
+    if !found {
+        return Err(ApiError::not_found(format!(
+            "{} is not spoofed for {}",
+            target_ip, addr
+        )));
+    }
+    healer
+        .request(HealRequest::Target(addr, target_ip))
+        .map_err(|e| ApiError::internal(format!("Failed to request healing: {}", e)))?;
+    info!(
+        "{} stopped spoofing {} for {}",
+        requester(&principal),
+        target_ip,
+        addr
+    );
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/config",
+    tag = "config",
+    responses((status = 200, description = "実行中に変更できる設定", body = ConfigSchema)),
+)]
+pub async fn get_config<C: ConfigRepository>(
+    Extension(config_repo): Extension<Arc<C>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = ConfigSchema::from(config_repo.get_config());
+    Ok((StatusCode::OK, Json(config)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/config",
+    tag = "config",
+    request_body = ConfigPatchSchema,
+    responses(
+        (status = 200, description = "変更後の設定", body = ConfigSchema),
+        (status = 400, description = "不正な設定", body = ProblemDetails, content_type = "application/problem+json"),
+    ),
+)]
+pub async fn patch_config<C: ConfigRepository>(
+    Extension(config_repo): Extension<Arc<C>>,
+    principal: Option<Extension<Principal>>,
+    ValidatedJson(payload): ValidatedJson<ConfigPatchSchema>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = config_repo.update(|config| payload.apply(config))?;
+    info!(
+        "{} changed the configuration: {:?}",
+        requester(&principal),
+        config.arp_proxy
+    );
+    Ok((StatusCode::OK, Json(ConfigSchema::from(config))))
+}
@@ -0,0 +1,111 @@
+use axum::{middleware, routing::get, Json, Router};
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Deprecated, OpenApi as OpenApiDocument,
+    },
+    Modify, OpenApi,
+};
+
+use super::{error, handlers, schema};
+use crate::{config::Role, web::auth::require_role};
+
+/// 旧APIのパス。OpenAPIドキュメント上でも非推奨とする
+const DEPRECATED_PATH_PREFIX: &str = "/api/allowed-mac/";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "arproxy-macfilter-agent administration API"),
+    paths(
+        handlers::all_allowedmac,
+        handlers::add_allowedmac,
+        handlers::delete_allowedmac,
+        handlers::list_allowedmacs,
+        handlers::get_allowedmac,
+        handlers::put_allowedmac,
+        handlers::patch_allowedmac,
+        handlers::remove_allowedmac,
+        handlers::bulk_allowedmacs,
+        handlers::list_deniedmacs,
+        handlers::get_deniedmac,
+        handlers::put_deniedmac,
+        handlers::remove_deniedmac,
+        handlers::all_hosts,
+        handlers::all_events,
+        handlers::all_arplog,
+        handlers::delete_arplog_sender,
+        handlers::delete_arplog_target,
+        handlers::get_config,
+        handlers::patch_config,
+    ),
+    components(schemas(
+        schema::AllowedMacSchema,
+        schema::AllowedMacMetadataSchema,
+        schema::AllowScheduleSchema,
+        schema::AllowedMacBulkSchema,
+        schema::AllowedMacBulkResponseSchema,
+        schema::DeniedMacSchema,
+        schema::DeniedMacMetadataSchema,
+        schema::HostSchema,
+        schema::EventSchema,
+        crate::repositories::event::EventKind,
+        schema::ArpLogSchema,
+        schema::ArpLogTargetSchema,
+        schema::ConfigSchema,
+        schema::ConfigPatchSchema,
+        schema::ArpProxyPatchSchema,
+        crate::config::ProxyMode,
+        error::ProblemDetails,
+        error::FieldError,
+    )),
+    modifiers(&ApiDocModifier),
+    security(("bearer" = [])),
+)]
+pub(super) struct ApiDoc;
+
+struct ApiDocModifier;
+
+impl Modify for ApiDocModifier {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        openapi.info.version = env!("CARGO_PKG_VERSION").to_string();
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if !path.starts_with(DEPRECATED_PATH_PREFIX) {
+                continue;
+            }
+            for operation in [&mut item.get, &mut item.post, &mut item.delete]
+                .into_iter()
+                .flatten()
+            {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+}
+
+/// 管理APIのOpenAPIドキュメント
+pub fn openapi() -> OpenApiDocument {
+    ApiDoc::openapi()
+}
+
+/// `/api/openapi.json` と、`swagger-ui` featureが有効な場合は `/api/swagger-ui` を提供する
+///
+/// ドキュメントには秘密情報を含まないため、認証なしで参照できる
+pub fn create_router() -> Router {
+    let document = openapi();
+    let router = Router::new().route(
+        "/api/openapi.json",
+        get(move || async move { Json(document) }),
+    );
+    #[cfg(feature = "swagger-ui")]
+    let router = router.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/api/swagger-ui")
+            .config(utoipa_swagger_ui::Config::from("/api/openapi.json")),
+    );
+    router.route_layer(middleware::from_fn_with_state(Role::Viewer, require_role))
+}
@@ -1,47 +1,248 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{delete, get, post},
+    http::HeaderValue,
+    middleware,
+    response::Response,
+    routing::{delete, get, patch, post, put},
     Extension, Router,
 };
 
-use crate::repositories::{
-    allowed_mac::AllowedMacRepository, arplog::ArpLogRepository, config::ConfigRepository,
+use crate::{
+    config::Role,
+    networks::healer::HealRequester,
+    repositories::{
+        allowed_mac::AllowedMacRepository, arplog::ArpLogRepository, config::ConfigRepository,
+        denied_mac::DeniedMacRepository, event::EventRepository, host::HostRepository,
+    },
 };
 
 use super::handlers;
+use crate::web::auth::require_role;
 
-pub fn create_router<C, M, A>(
-    _config_repo: Arc<C>,
+pub fn create_router<C, M, A, H, E, D>(
+    config_repo: Arc<C>,
     allowedmac_repo: Arc<M>,
-    _arplog_repo: Arc<A>,
+    arplog_repo: Arc<A>,
+    host_repo: Arc<H>,
+    event_repo: Arc<E>,
+    deniedmac_repo: Arc<D>,
+    healer: HealRequester,
 ) -> Router
 where
     C: ConfigRepository,
     M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    E: EventRepository,
+    D: DeniedMacRepository,
 {
-    let app = Router::new().nest("/allowed-mac", create_allowedmac_router(allowedmac_repo));
-    app
+    Router::new()
+        .nest(
+            "/allowed-mac",
+            create_allowedmac_router(allowedmac_repo.clone(), healer.clone()),
+        )
+        .nest(
+            "/v1/allowed-macs",
+            create_allowedmacs_router(allowedmac_repo, healer.clone()),
+        )
+        .nest(
+            "/v1/denied-macs",
+            create_deniedmacs_router(deniedmac_repo, config_repo.clone(), healer.clone()),
+        )
+        .nest("/arplog", create_arplog_router(arplog_repo, healer))
+        .nest("/config", create_config_router(config_repo))
+        .nest("/hosts", create_host_router(host_repo))
+        .nest("/events", create_event_router(event_repo))
 }
 
-fn create_allowedmac_router<M>(allowedmac_repo: Arc<M>) -> Router
+fn create_allowedmac_router<M>(allowedmac_repo: Arc<M>, healer: HealRequester) -> Router
 where
     M: AllowedMacRepository,
 {
-    let app = Router::new()
-        .route("/all", get(handlers::all_allowedmac::<M>))
-        .route("/add", post(handlers::add_allowedmac::<M>))
-        .route("/delete", delete(handlers::delete_allowedmac::<M>))
-        .layer(Extension(allowedmac_repo.clone()));
-    app
+    Router::new()
+        .route(
+            "/all",
+            get(handlers::all_allowedmac::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/add",
+            post(handlers::add_allowedmac::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .route(
+            "/delete",
+            delete(handlers::delete_allowedmac::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .layer(Extension(allowedmac_repo.clone()))
+        .layer(Extension(healer))
+        .layer(middleware::map_response(deprecated))
+}
+
+/// `/api/allowed-mac` の後継となるリソース指向のAPI
+fn create_allowedmacs_router<M>(allowedmac_repo: Arc<M>, healer: HealRequester) -> Router
+where
+    M: AllowedMacRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::list_allowedmacs::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/bulk",
+            post(handlers::bulk_allowedmacs::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .route(
+            "/:mac_address",
+            get(handlers::get_allowedmac::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/:mac_address",
+            put(handlers::put_allowedmac::<M>)
+                .patch(handlers::patch_allowedmac::<M>)
+                .delete(handlers::remove_allowedmac::<M>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .layer(Extension(allowedmac_repo))
+        .layer(Extension(healer))
+}
+
+/// 拒否リストモードで偽装の対象とするMACアドレスのAPI
+fn create_deniedmacs_router<D, C>(
+    deniedmac_repo: Arc<D>,
+    config_repo: Arc<C>,
+    healer: HealRequester,
+) -> Router
+where
+    D: DeniedMacRepository,
+    C: ConfigRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::list_deniedmacs::<D>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/:mac_address",
+            get(handlers::get_deniedmac::<D>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/:mac_address",
+            put(handlers::put_deniedmac::<D>)
+                .delete(handlers::remove_deniedmac::<C, D>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .layer(Extension(deniedmac_repo))
+        .layer(Extension(config_repo))
+        .layer(Extension(healer))
+}
+
+/// 非推奨のAPIであることと後継のAPIをレスポンスヘッダで知らせる
+async fn deprecated(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    headers.insert(
+        "link",
+        HeaderValue::from_static("</api/v1/allowed-macs>; rel=\"successor-version\""),
+    );
+    response
+}
+
+fn create_arplog_router<A>(arplog_repo: Arc<A>, healer: HealRequester) -> Router
+where
+    A: ArpLogRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::all_arplog::<A>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/:mac_address",
+            delete(handlers::delete_arplog_sender::<A>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .route(
+            "/:mac_address/:target_ip",
+            delete(handlers::delete_arplog_target::<A>)
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        )
+        .layer(Extension(arplog_repo))
+        .layer(Extension(healer))
+}
+
+fn create_config_router<C>(config_repo: Arc<C>) -> Router
+where
+    C: ConfigRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::get_config::<C>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .route(
+            "/",
+            patch(handlers::patch_config::<C>)
+                .route_layer(middleware::from_fn_with_state(Role::Admin, require_role)),
+        )
+        .layer(Extension(config_repo))
+}
+
+fn create_host_router<H>(host_repo: Arc<H>) -> Router
+where
+    H: HostRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::all_hosts::<H>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .layer(Extension(host_repo))
+}
+
+fn create_event_router<E>(event_repo: Arc<E>) -> Router
+where
+    E: EventRepository,
+{
+    Router::new()
+        .route(
+            "/",
+            get(handlers::all_events::<E>)
+                .route_layer(middleware::from_fn_with_state(Role::Viewer, require_role)),
+        )
+        .layer(Extension(event_repo))
 }
 
 #[cfg(test)]
 mod test {
-    use std::{str::FromStr, sync::Arc};
+    use std::{net::Ipv4Addr, str::FromStr, sync::Arc, time::SystemTime};
 
-    use crate::repositories::allowed_mac::{AllowedMacRepository, AllowedMacRepositoryForMemory};
+    use crate::{
+        config::ProxyMode,
+        networks::healer::{HealRequest, HealRequester},
+        repositories::{
+            allowed_mac::{
+                Allowance, AllowedMac, AllowedMacRepository, AllowedMacRepositoryForMemory,
+                MacPrefix,
+            },
+            arplog::{ArpLog, ArpLogRepository, ArpLogRepositoryForMemory},
+            config::{ConfigRepository, ConfigRepositoryForMemory},
+            denied_mac::{DeniedMacRepository, DeniedMacRepositoryForMemory},
+            event::{Event, EventKind, EventRepository, EventRepositoryForMemory},
+            host::{HostRepository, HostRepositoryForMemory},
+        },
+    };
     use axum::{
         body::{Body, Bytes},
         http::{self, Method, Request, StatusCode},
@@ -54,19 +255,98 @@ mod test {
     use validator::Validate;
 
     use crate::web::api::schema::{
-        AllowedMacDeleteSchema, AllowedMacPostResponseSchema, AllowedMacPostSchema,
+        AllowedMacBulkResponseSchema, AllowedMacDeleteSchema, AllowedMacPostResponseSchema,
+        AllowedMacPostSchema, AllowedMacSchema, ArpLogSchema, BulkOutcomeSchema, ConfigSchema,
+        DeniedMacSchema, EventSchema, HostSchema,
     };
+    use crate::web::api::{error::ProblemDetails, openapi::openapi};
 
-    use super::create_allowedmac_router;
+    use super::{
+        create_allowedmac_router, create_allowedmacs_router, create_arplog_router,
+        create_config_router, create_deniedmacs_router, create_event_router, create_host_router,
+        create_router,
+    };
+
+    /// 現在許可されているか。ARP Probe (0.0.0.0) はどのエントリでも認められるため送信元IPアドレスは問わない
+    fn is_allowed(repo: &impl AllowedMacRepository, address: &MacAddr) -> bool {
+        repo.check(address, &Ipv4Addr::UNSPECIFIED.into(), SystemTime::now())
+            .unwrap()
+            == Allowance::Allowed
+    }
 
     fn create_dummy_allowedmac_repo() -> AllowedMacRepositoryForMemory {
         let repo = AllowedMacRepositoryForMemory::new();
-        repo.add(MacAddr::new(2, 0, 0, 0, 0xf, 1)).expect("SyncErr");
-        repo.add(MacAddr::new(2, 0, 0, 0, 0xf, 2)).expect("SyncErr");
-        repo.add(MacAddr::new(2, 0, 0, 0, 0xf, 3)).expect("SyncErr");
+        repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 1)))
+            .expect("SyncErr");
+        repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 2)))
+            .expect("SyncErr");
+        repo.add(AllowedMac::new(MacAddr::new(2, 0, 0, 0, 0xf, 3)))
+            .expect("SyncErr");
+        repo
+    }
+
+    fn create_dummy_host_repo() -> HostRepositoryForMemory {
+        let repo = HostRepositoryForMemory::new();
+        repo.put(
+            MacAddr::new(2, 0, 0, 0, 0xf, 1),
+            Ipv4Addr::new(192, 168, 0, 1),
+            true,
+        )
+        .expect("SyncErr");
+        repo.put(
+            MacAddr::new(2, 0, 0, 0, 0xf, 2),
+            Ipv4Addr::new(192, 168, 0, 2),
+            false,
+        )
+        .expect("SyncErr");
+        repo.put(
+            MacAddr::new(2, 0, 0, 0, 0xf, 2),
+            Ipv4Addr::new(192, 168, 0, 3),
+            false,
+        )
+        .expect("SyncErr");
+        repo
+    }
+
+    fn create_dummy_arplog_repo() -> ArpLogRepositoryForMemory {
+        let repo = ArpLogRepositoryForMemory::new();
+        let sender_mac = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        let sender_ip = Ipv4Addr::new(192, 168, 0, 2);
+        for target in [10, 1] {
+            repo.put(ArpLog::new(
+                sender_mac,
+                sender_ip,
+                Ipv4Addr::new(192, 168, 0, target),
+            ))
+            .expect("SyncErr");
+        }
+        repo.put(ArpLog::new(
+            MacAddr::new(2, 0, 0, 0, 0xf, 1),
+            Ipv4Addr::new(192, 168, 0, 3),
+            Ipv4Addr::new(192, 168, 0, 1),
+        ))
+        .expect("SyncErr");
         repo
     }
 
+    fn create_dummy_config_repo() -> ConfigRepositoryForMemory {
+        let config = serde_json::from_value(serde_json::json!({
+            "interface": "eth0",
+            "arp_proxy": {
+                "proxy_allowed_macs": false,
+                "arp_reply_interval": 5,
+                "arp_reply_duration": 60
+            },
+            "administration": {
+                "enable_api": true,
+                "listen_address": "127.0.0.1",
+                "listen_port": 3000
+            }
+        }))
+        .unwrap();
+        ConfigRepositoryForMemory::new(config)
+    }
+
     async fn request_oneshot_empty(
         app: Router,
         method: http::Method,
@@ -109,7 +389,7 @@ mod test {
     #[tokio::test]
     async fn should_getall_allowedmac() {
         let repo = create_dummy_allowedmac_repo();
-        let app = create_allowedmac_router(Arc::new(repo));
+        let app = create_allowedmac_router(Arc::new(repo), HealRequester::new().0);
         // ステータスコード・レスポンスボディが正当か
         let (status, body) = request_oneshot_empty(app, http::Method::GET, "/all").await;
         assert_eq!(status, StatusCode::OK);
@@ -128,9 +408,11 @@ mod test {
     #[tokio::test]
     async fn should_add_allowedmac() {
         let repo = create_dummy_allowedmac_repo();
-        let app = create_allowedmac_router(Arc::new(repo.clone()));
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_allowedmac_router(Arc::new(repo.clone()), healer);
         let req_body = AllowedMacPostSchema {
             mac_address: MacAddr::new(2, 0, 0, 0, 0xf, 5).to_string(),
+            ..Default::default()
         };
         let req_body_raw = serde_json::to_vec(&req_body).unwrap();
         // ステータスコード・レスポンスボディが正当か
@@ -139,23 +421,30 @@ mod test {
         assert_eq!(status, StatusCode::CREATED);
         let res_body = serde_json::from_slice::<AllowedMacPostResponseSchema>(&body).unwrap();
         res_body.validate().expect("Unexpected response");
-        assert_eq!(res_body, req_body);
+        assert_eq!(res_body.mac_address, req_body.mac_address);
+        assert!(res_body.created_at.is_some());
         // レポジトリに追加済みか
         let maddrs = repo.getall().unwrap();
         assert_eq!(maddrs.len(), 4);
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 5)));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 5).into()));
+        // 修復が要求されているか
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Reevaluate(MacAddr::new(2, 0, 0, 0, 0xf, 5).into())
+        );
     }
 
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn should_delete_allowedmac() {
         let repo = create_dummy_allowedmac_repo();
-        let app = create_allowedmac_router(Arc::new(repo.clone()));
+        let app = create_allowedmac_router(Arc::new(repo.clone()), HealRequester::new().0);
         let req_body = AllowedMacDeleteSchema {
             mac_address: MacAddr::new(2, 0, 0, 0, 0xf, 2).to_string(),
+            ..Default::default()
         };
         let req_body = serde_json::to_vec(&req_body).unwrap();
         // ステータスコードが正当か
@@ -164,16 +453,516 @@ mod test {
         // レポジトリから削除されているか
         let maddrs = repo.getall().unwrap();
         assert_eq!(maddrs.len(), 2);
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)));
-        assert!(!maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3)));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into()));
+        assert!(!maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3).into()));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn legacy_allowedmac_is_deprecated_and_idempotent() {
+        let repo = create_dummy_allowedmac_repo();
+        let app = create_allowedmac_router(Arc::new(repo.clone()), HealRequester::new().0);
+        let res = app
+            .clone()
+            .oneshot(Request::builder().uri("/all").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()["deprecation"], "true");
+        assert_eq!(
+            res.headers()["link"],
+            r#"</api/v1/allowed-macs>; rel="successor-version""#
+        );
+        // 登録済みのものの追加・登録されていないものの削除も成功する
+        let req_body = serde_json::to_vec(&AllowedMacSchema {
+            mac_address: MacAddr::new(2, 0, 0, 0, 0xf, 1).to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let (status, _) = request_oneshot_json(app.clone(), Method::POST, "/add", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let req_body = serde_json::to_vec(&AllowedMacSchema {
+            mac_address: MacAddr::new(2, 0, 0, 0, 0xf, 9).to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let (status, _) = request_oneshot_json(app, Method::DELETE, "/delete", req_body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(repo.getall().unwrap().len(), 3);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_allowedmacs_resource() {
+        let repo = create_dummy_allowedmac_repo();
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), healer);
+        // 一覧はソートされている
+        let (status, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let addrs = serde_json::from_slice::<Vec<AllowedMacSchema>>(&body).unwrap();
+        let addrs: Vec<&str> = addrs.iter().map(|a| a.mac_address.as_str()).collect();
+        assert_eq!(
+            addrs,
+            vec![
+                "02:00:00:00:0f:01",
+                "02:00:00:00:0f:02",
+                "02:00:00:00:0f:03"
+            ]
+        );
+        // 個別の取得
+        let (status, body) =
+            request_oneshot_empty(app.clone(), Method::GET, "/02:00:00:00:0f:01").await;
+        assert_eq!(status, StatusCode::OK);
+        let addr = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(addr.mac_address, "02:00:00:00:0f:01");
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::GET, "/02:00:00:00:0f:05").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // 追加
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::PUT, "/02:00:00:00:0f:05").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 5)));
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Reevaluate(MacAddr::new(2, 0, 0, 0, 0xf, 5).into())
+        );
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::PUT, "/02:00:00:00:0f:05").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // Content-Typeのない本文は既定値として扱わない
+        let req = Request::builder()
+            .method(Method::PUT)
+            .uri("/02:00:00:00:0f:06")
+            .body(Body::from(r#"{"ttl":60}"#))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 6)));
+        assert!(heal_requests.try_recv().is_err());
+        // 削除
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::DELETE, "/02:00:00:00:0f:05").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 5)));
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::DELETE, "/02:00:00:00:0f:05").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // 無効な入力
+        for method in [Method::GET, Method::PUT, Method::DELETE] {
+            let (status, _) = request_oneshot_empty(app.clone(), method, "/hello").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(repo.getall().unwrap().len(), 3);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_allowedmac_metadata() {
+        let repo = create_dummy_allowedmac_repo();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), HealRequester::new().0);
+        // 付帯情報を付けて追加
+        let req_body = br#"{ "label": "printer", "owner": "alice" }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(created.label.as_deref(), Some("printer"));
+        assert_eq!(created.owner.as_deref(), Some("alice"));
+        assert!(created.created_at.is_some());
+        // 付帯情報の変更
+        let req_body = br#"{ "label": "", "note": "2F" }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PATCH, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let modified = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(modified.label, None);
+        assert_eq!(modified.owner.as_deref(), Some("alice"));
+        assert_eq!(modified.note.as_deref(), Some("2F"));
+        assert_eq!(modified.created_at, created.created_at);
+        let (_, body) = request_oneshot_empty(app.clone(), Method::GET, "/02:00:00:00:0f:05").await;
+        assert_eq!(
+            serde_json::from_slice::<AllowedMacSchema>(&body).unwrap(),
+            modified
+        );
+        // 存在しないもの・無効な入力
+        let cases = [
+            (
+                "/02:00:00:00:0f:09",
+                r#"{ "note": "x" }"#,
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "/02:00:00:00:0f:05",
+                r#"{ "color": "red" }"#,
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for (path, req_body, expected) in cases {
+            let (status, _) = request_oneshot_json(
+                app.clone(),
+                Method::PATCH,
+                path,
+                req_body.as_bytes().to_vec(),
+            )
+            .await;
+            assert_eq!(status, expected, "{}", path);
+        }
+        let long_label = format!(r#"{{ "label": "{}" }}"#, "a".repeat(257));
+        let (status, _) = request_oneshot_json(
+            app.clone(),
+            Method::PUT,
+            "/02:00:00:00:0f:06",
+            long_label.into_bytes(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 6)));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_allowedmac_expiry() {
+        let repo = create_dummy_allowedmac_repo();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), HealRequester::new().0);
+        // ttlを指定して追加
+        let req_body = br#"{ "label": "visitor", "ttl": 3600 }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        let expires_at = created.expires_at.unwrap();
+        let remaining = expires_at - created.created_at.unwrap();
+        assert!((3599..=3600).contains(&remaining.num_seconds()));
+        assert!(!String::from_utf8_lossy(&body).contains("ttl"));
+        // 有効期限の変更と解除
+        let req_body = br#"{ "expires_at": "2999-01-01T00:00:00Z" }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PATCH, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let modified = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(
+            modified.expires_at,
+            Some("2999-01-01T00:00:00Z".parse().unwrap())
+        );
+        let req_body = br#"{ "expires_at": null }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PATCH, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let modified = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(modified.expires_at, None);
+        assert_eq!(modified.label.as_deref(), Some("visitor"));
+        // 過去の日時、両方の指定、0秒は受け付けない
+        let cases = [
+            (r#"{ "expires_at": "2000-01-01T00:00:00Z" }"#, "expires_at"),
+            (
+                r#"{ "expires_at": "2999-01-01T00:00:00Z", "ttl": 60 }"#,
+                "__all__",
+            ),
+            (r#"{ "ttl": 0 }"#, "ttl"),
+        ];
+        for (req_body, field) in cases {
+            let (status, body) = request_oneshot_json(
+                app.clone(),
+                Method::PUT,
+                "/02:00:00:00:0f:06",
+                req_body.as_bytes().to_vec(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", req_body);
+            let problem = serde_json::from_slice::<ProblemDetails>(&body).unwrap();
+            assert!(problem.errors.contains_key(field), "{:?}", problem.errors);
+        }
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 6)));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_allowedmac_schedules() {
+        let repo = create_dummy_allowedmac_repo();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), HealRequester::new().0);
+        let req_body = br#"{ "schedules": [
+            { "weekdays": ["mon", "Tuesday", "Wed"], "start": "08:30", "end": "16:00" }
+        ] }"#
+            .to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(created.schedules.len(), 1);
+        assert_eq!(created.schedules[0].weekdays.len(), 3);
+        assert_eq!(created.schedules[0].start.to_string(), "08:30:00");
+        let entry = repo.get(&MacAddr::new(2, 0, 0, 0, 0xf, 5).into()).unwrap();
+        assert_eq!(entry.schedules.len(), 1);
+        // 空の配列でスケジュールを外す
+        let req_body = br#"{ "schedules": [] }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PATCH, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let modified = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert!(modified.schedules.is_empty());
+        // 不正なスケジュール
+        let cases = [
+            r#"{ "schedules": [{ "start": "08:30", "end": "08:30" }] }"#,
+            r#"{ "schedules": [{ "weekdays": ["someday"], "start": "08:30", "end": "16:00" }] }"#,
+            r#"{ "schedules": [{ "start": "25:00", "end": "16:00" }] }"#,
+        ];
+        for req_body in cases {
+            let (status, _) = request_oneshot_json(
+                app.clone(),
+                Method::PUT,
+                "/02:00:00:00:0f:06",
+                req_body.as_bytes().to_vec(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", req_body);
+        }
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 6)));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_deniedmacs() {
+        let repo = DeniedMacRepositoryForMemory::new();
+        let config_repo = create_dummy_config_repo();
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_deniedmacs_router(
+            Arc::new(repo.clone()),
+            Arc::new(config_repo.clone()),
+            healer,
+        );
+        let req_body = br#"{ "label": "stolen laptop" }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:01", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<DeniedMacSchema>(&body).unwrap();
+        assert_eq!(created.mac_address, "02:00:00:00:0f:01");
+        assert_eq!(created.label.as_deref(), Some("stolen laptop"));
+        assert!(created.created_at.is_some());
+        assert!(repo.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)).unwrap());
+        // 本文を省略してプレフィックスを追加
+        let (status, _) = request_oneshot_empty(app.clone(), Method::PUT, "/00:1b:63:*").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(repo
+            .contains(&MacAddr::new(0, 0x1b, 0x63, 1, 2, 3))
+            .unwrap());
+        let (status, _) = request_oneshot_empty(app.clone(), Method::PUT, "/00:1b:63:*").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = serde_json::from_slice::<Vec<DeniedMacSchema>>(&body).unwrap();
+        assert_eq!(entries.len(), 2);
+        let (status, _) = request_oneshot_empty(app.clone(), Method::GET, "/00:1b:63:*").await;
+        assert_eq!(status, StatusCode::OK);
+        // 削除
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::DELETE, "/02:00:00:00:0f:01").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!repo.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)).unwrap());
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::DELETE, "/02:00:00:00:0f:01").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // 許可リストモードでは修復しない
+        assert!(heal_requests.try_recv().is_err());
+        // 拒否リストモードでは削除したMACアドレスへの偽装を直ちに打ち消す
+        config_repo
+            .update(|config| config.arp_proxy.mode = ProxyMode::Denylist)
+            .unwrap();
+        let (status, _) = request_oneshot_empty(app.clone(), Method::DELETE, "/00:1b:63:*").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Reevaluate(MacPrefix::from_str("00:1b:63:*").unwrap())
+        );
+        // 不正な入力
+        let (status, _) = request_oneshot_empty(app.clone(), Method::PUT, "/hello").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let req_body = br#"{ "owner": "someone" }"#.to_vec();
+        let (status, _) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:02", req_body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn should_manage_allowedmac_allowed_ips() {
+        let repo = create_dummy_allowedmac_repo();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), HealRequester::new().0);
+        let addr = MacAddr::new(2, 0, 0, 0, 0xf, 5);
+        let req_body = br#"{ "allowed_ips": ["192.168.0.10", "10.0.1.0/24"] }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PUT, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert_eq!(
+            created["allowed_ips"],
+            serde_json::json!(["192.168.0.10/32", "10.0.1.0/24"])
+        );
+        assert_eq!(
+            repo.check(
+                &addr,
+                &Ipv4Addr::new(192, 168, 0, 11).into(),
+                SystemTime::now()
+            )
+            .unwrap(),
+            Allowance::UnexpectedIp
+        );
+        // 空の配列で制限を外す
+        let req_body = br#"{ "allowed_ips": [] }"#.to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::PATCH, "/02:00:00:00:0f:05", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let modified = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert!(modified.allowed_ips.is_empty());
+        assert_eq!(
+            repo.check(
+                &addr,
+                &Ipv4Addr::new(192, 168, 0, 11).into(),
+                SystemTime::now()
+            )
+            .unwrap(),
+            Allowance::Allowed
+        );
+        // 不正なIPアドレス
+        for req_body in [
+            r#"{ "allowed_ips": ["192.168.0.256"] }"#,
+            r#"{ "allowed_ips": ["10.0.1.0/33"] }"#,
+            r#"{ "allowed_ips": ["fe80::1"] }"#,
+        ] {
+            let (status, _) = request_oneshot_json(
+                app.clone(),
+                Method::PUT,
+                "/02:00:00:00:0f:06",
+                req_body.as_bytes().to_vec(),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", req_body);
+        }
+        assert!(!is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0xf, 6)));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_manage_allowedmac_prefixes() {
+        let repo = create_dummy_allowedmac_repo();
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), healer);
+        // OUI単位で許可
+        let (status, body) = request_oneshot_empty(app.clone(), Method::PUT, "/00:1B:63:*").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(created.mac_address, "00:1b:63:*");
+        assert!(is_allowed(&repo, &MacAddr::new(0, 0x1b, 0x63, 1, 2, 3)));
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Reevaluate(MacPrefix::from_str("00:1b:63:*").unwrap())
+        );
+        // 長さを指定する表記はスラッシュをエンコードする
+        let (status, body) =
+            request_oneshot_empty(app.clone(), Method::PUT, "/02:00:00:00:00:00%2F44").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let created = serde_json::from_slice::<AllowedMacSchema>(&body).unwrap();
+        assert_eq!(created.mac_address, "02:00:00:00:00:00/44");
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::GET, "/02:00:00:00:00:00%2F44").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(is_allowed(&repo, &MacAddr::new(2, 0, 0, 0, 0, 0x0f)));
+        let (_, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        let entries = serde_json::from_slice::<Vec<AllowedMacSchema>>(&body).unwrap();
+        assert_eq!(entries.len(), 5);
+        // 削除
+        let (status, _) = request_oneshot_empty(app.clone(), Method::DELETE, "/00:1b:63:*").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!is_allowed(&repo, &MacAddr::new(0, 0x1b, 0x63, 1, 2, 3)));
+        // 不正なプレフィックス
+        for path in [
+            "/00:1b:63",
+            "/02:00:00:00:00:01%2F40",
+            "/02:00:00:00:00:00%2F49",
+        ] {
+            let (status, _) = request_oneshot_empty(app.clone(), Method::PUT, path).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_bulk_update_allowedmacs() {
+        let repo = create_dummy_allowedmac_repo();
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_allowedmacs_router(Arc::new(repo.clone()), healer);
+        // add
+        let req_body = br#"{ "operation": "add", "entries": [
+            { "mac_address": "02:00:00:00:0f:01" }, { "mac_address": "02:00:00:00:0f:05" }
+        ] }"#
+            .to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::POST, "/bulk", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let res = serde_json::from_slice::<AllowedMacBulkResponseSchema>(&body).unwrap();
+        let results: Vec<BulkOutcomeSchema> = res.results.iter().map(|r| r.result).collect();
+        assert_eq!(
+            results,
+            vec![BulkOutcomeSchema::Unchanged, BulkOutcomeSchema::Added]
+        );
+        assert_eq!(res.results[1].mac_address, "02:00:00:00:0f:05");
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Reevaluate(MacAddr::new(2, 0, 0, 0, 0xf, 5).into())
+        );
+        assert!(heal_requests.try_recv().is_err());
+        // remove
+        let req_body = br#"{ "operation": "remove", "entries": [
+            { "mac_address": "02:00:00:00:0f:05" }, { "mac_address": "02:00:00:00:0f:09" }
+        ] }"#
+            .to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::POST, "/bulk", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let res = serde_json::from_slice::<AllowedMacBulkResponseSchema>(&body).unwrap();
+        let results: Vec<BulkOutcomeSchema> = res.results.iter().map(|r| r.result).collect();
+        assert_eq!(
+            results,
+            vec![BulkOutcomeSchema::Removed, BulkOutcomeSchema::NotFound]
+        );
+        // replace
+        let req_body = br#"{ "operation": "replace", "entries": [
+            { "mac_address": "02:00:00:00:0f:02" }
+        ] }"#
+            .to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::POST, "/bulk", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let res = serde_json::from_slice::<AllowedMacBulkResponseSchema>(&body).unwrap();
+        assert_eq!(res.removed, vec!["02:00:00:00:0f:01", "02:00:00:00:0f:03"]);
+        assert_eq!(
+            repo.getall().unwrap(),
+            vec![MacAddr::new(2, 0, 0, 0, 0xf, 2).into()]
+        );
+        // 1件でも不正な項目があれば何も変更しない
+        let req_body = br#"{ "operation": "replace", "entries": [
+            { "mac_address": "02:00:00:00:0f:07" }, { "mac_address": "hello, world" }
+        ] }"#
+            .to_vec();
+        let (status, body) =
+            request_oneshot_json(app.clone(), Method::POST, "/bulk", req_body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let problem = serde_json::from_slice::<ProblemDetails>(&body).unwrap();
+        assert_eq!(
+            problem.errors["entries[1].mac_address"][0].code,
+            "invalid_mac_address"
+        );
+        assert_eq!(
+            repo.getall().unwrap(),
+            vec![MacAddr::new(2, 0, 0, 0, 0xf, 2).into()]
+        );
     }
 
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn invalid_input_allowedmac() {
         let repo = create_dummy_allowedmac_repo();
-        let app = create_allowedmac_router(Arc::new(repo.clone()));
+        let app = create_allowedmac_router(Arc::new(repo.clone()), HealRequester::new().0);
         // 無効な入力：非JSON, 不正なMACアドレス
         let req_bodys = vec![
             "{ maddr: true }".to_string().into_bytes(),
@@ -192,12 +981,287 @@ mod test {
                 request_oneshot_json(app.clone(), Method::POST, "/add", req_body.clone()).await;
             assert_eq!(status, StatusCode::BAD_REQUEST);
         }
+        // 検証エラーはフィールドごとに返される
+        let req_body = r#"{ "mac_address": "hello, world" }"#.to_string().into_bytes();
+        let (_, body) = request_oneshot_json(app.clone(), Method::POST, "/add", req_body).await;
+        let problem = serde_json::from_slice::<ProblemDetails>(&body).unwrap();
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.errors["mac_address"][0].code, "invalid_mac_address");
 
         // レポジトリに変化がないか
         let maddrs = repo.getall().unwrap();
         assert_eq!(maddrs.len(), 3);
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2)));
-        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3)));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 1).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 2).into()));
+        assert!(maddrs.contains(&MacAddr::new(2, 0, 0, 0, 0xf, 3).into()));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_list_events() {
+        let repo = EventRepositoryForMemory::new();
+        let mac1 = MacAddr::new(2, 0, 0, 0, 0xf, 1);
+        let mac2 = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        let ips = vec![
+            Ipv4Addr::new(192, 168, 0, 10),
+            Ipv4Addr::new(192, 168, 0, 11),
+        ];
+        repo.push(Event::new(EventKind::DuplicateMac, mac1, ips.clone()))
+            .unwrap();
+        repo.push(Event {
+            revoked: true,
+            ..Event::new(EventKind::Flapping, mac1, ips.clone())
+        })
+        .unwrap();
+        repo.push(Event::new(EventKind::UnexpectedIp, mac2, vec![ips[0]]))
+            .unwrap();
+        let app = create_event_router(Arc::new(repo));
+        let (status, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let events = serde_json::from_slice::<Vec<EventSchema>>(&body).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].id, 1);
+        assert_eq!(events[0].kind, EventKind::DuplicateMac);
+        assert_eq!(events[0].ip_addresses, ips);
+        assert!(events[1].revoked);
+        // 絞り込み
+        let (_, body) =
+            request_oneshot_empty(app.clone(), Method::GET, "/?mac_address=02:00:00:00:0f:02")
+                .await;
+        let events = serde_json::from_slice::<Vec<EventSchema>>(&body).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EventKind::UnexpectedIp);
+        let (_, body) = request_oneshot_empty(app.clone(), Method::GET, "/?kind=flapping").await;
+        let events = serde_json::from_slice::<Vec<EventSchema>>(&body).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, 2);
+        for query in ["/?mac_address=hello", "/?kind=unknown"] {
+            let (status, _) = request_oneshot_empty(app.clone(), Method::GET, query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_getall_hosts() {
+        let app = create_host_router(Arc::new(create_dummy_host_repo()));
+        // ステータスコード・レスポンスボディが正当か
+        let (status, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let hosts = serde_json::from_slice::<Vec<HostSchema>>(&body).unwrap();
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(hosts[0].ip_address, Ipv4Addr::new(192, 168, 0, 1));
+        assert!(hosts[0].allowed);
+        assert!(!hosts[1].allowed);
+        // 絞り込み
+        let (status, body) =
+            request_oneshot_empty(app.clone(), Method::GET, "/?mac_address=02:00:00:00:0f:02")
+                .await;
+        assert_eq!(status, StatusCode::OK);
+        let hosts = serde_json::from_slice::<Vec<HostSchema>>(&body).unwrap();
+        assert_eq!(hosts.len(), 2);
+        let (status, body) =
+            request_oneshot_empty(app.clone(), Method::GET, "/?ip_address=192.168.0.3").await;
+        assert_eq!(status, StatusCode::OK);
+        let hosts = serde_json::from_slice::<Vec<HostSchema>>(&body).unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].mac_address, "02:00:00:00:0f:02");
+        // 無効な入力
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::GET, "/?mac_address=hello").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = request_oneshot_empty(app, Method::GET, "/?ip_address=hello").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_getall_arplog() {
+        let app =
+            create_arplog_router(Arc::new(create_dummy_arplog_repo()), HealRequester::new().0);
+        // 送信元MACアドレス毎にまとめられているか
+        let (status, body) = request_oneshot_empty(app, Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let senders = serde_json::from_slice::<Vec<ArpLogSchema>>(&body).unwrap();
+        assert_eq!(senders.len(), 2);
+        assert_eq!(senders[0].sender_mac, "02:00:00:00:0f:01");
+        assert_eq!(senders[1].sender_mac, "02:00:00:00:0f:02");
+        assert_eq!(senders[1].sender_ip, Ipv4Addr::new(192, 168, 0, 2));
+        let targets: Vec<Ipv4Addr> = senders[1]
+            .targets
+            .iter()
+            .map(|target| target.target_ip)
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                Ipv4Addr::new(192, 168, 0, 1),
+                Ipv4Addr::new(192, 168, 0, 10)
+            ]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_delete_arplog() {
+        let (healer, mut heal_requests) = HealRequester::new();
+        let app = create_arplog_router(Arc::new(create_dummy_arplog_repo()), healer);
+        let sender_mac = MacAddr::new(2, 0, 0, 0, 0xf, 2);
+        // 送信元の削除
+        let (status, _) =
+            request_oneshot_empty(app.clone(), Method::DELETE, "/02:00:00:00:0f:02").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Sender(sender_mac)
+        );
+        // 送信元と目標IPアドレスの組の削除
+        let (status, _) = request_oneshot_empty(
+            app.clone(),
+            Method::DELETE,
+            "/02:00:00:00:0f:02/192.168.0.10",
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(
+            heal_requests.try_recv().unwrap(),
+            HealRequest::Target(sender_mac, Ipv4Addr::new(192, 168, 0, 10))
+        );
+        // 存在しないもの・無効な入力
+        let cases = [
+            ("/02:00:00:00:0f:09", StatusCode::NOT_FOUND),
+            ("/02:00:00:00:0f:02/192.168.0.9", StatusCode::NOT_FOUND),
+            ("/hello", StatusCode::BAD_REQUEST),
+            ("/02:00:00:00:0f:02/hello", StatusCode::BAD_REQUEST),
+        ];
+        for (path, expected) in cases {
+            let (status, _) = request_oneshot_empty(app.clone(), Method::DELETE, path).await;
+            assert_eq!(status, expected, "{}", path);
+        }
+        assert!(heal_requests.try_recv().is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_get_and_patch_config() {
+        let repo = create_dummy_config_repo();
+        let app = create_config_router(Arc::new(repo.clone()));
+        let (status, body) = request_oneshot_empty(app.clone(), Method::GET, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let config = serde_json::from_slice::<ConfigSchema>(&body).unwrap();
+        assert_eq!(config.arp_proxy.arp_reply_interval, 5);
+        // 指定した項目のみ変更される
+        let req_body = br#"{ "arp_proxy": { "arp_reply_interval": 10 } }"#.to_vec();
+        let (status, body) = request_oneshot_json(app.clone(), Method::PATCH, "/", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        let config = serde_json::from_slice::<ConfigSchema>(&body).unwrap();
+        assert_eq!(config.arp_proxy.arp_reply_interval, 10);
+        assert_eq!(config.arp_proxy.arp_reply_duration, 60);
+        assert_eq!(repo.get_config().arp_proxy, config.arp_proxy);
+        // 無効な入力：範囲外の値, durationがintervalより短い, 未知の項目
+        let req_bodys = [
+            br#"{ "arp_proxy": { "arp_reply_interval": 0 } }"#.to_vec(),
+            br#"{ "arp_proxy": { "arp_reply_duration": 5 } }"#.to_vec(),
+            br#"{ "arp_proxy": { "arp_reply_intervall": 5 } }"#.to_vec(),
+            br#"{ "interface": "eth1" }"#.to_vec(),
+        ];
+        for req_body in req_bodys {
+            let (status, body) =
+                request_oneshot_json(app.clone(), Method::PATCH, "/", req_body).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let problem = serde_json::from_slice::<ProblemDetails>(&body).unwrap();
+            assert!(problem.detail.is_some());
+        }
+        // 設定の検証エラーは理由が返される
+        let req_body = br#"{ "arp_proxy": { "arp_reply_interval": 0 } }"#.to_vec();
+        let (_, body) = request_oneshot_json(app.clone(), Method::PATCH, "/", req_body).await;
+        let problem = serde_json::from_slice::<ProblemDetails>(&body).unwrap();
+        assert!(problem.detail.unwrap().contains("arp_reply_interval"));
+        assert_eq!(repo.get_config().arp_proxy, config.arp_proxy);
+        // 方式の切り替え
+        assert_eq!(config.arp_proxy.mode, ProxyMode::Allowlist);
+        let req_body = br#"{ "arp_proxy": { "mode": "denylist" } }"#.to_vec();
+        let (status, _) = request_oneshot_json(app.clone(), Method::PATCH, "/", req_body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(repo.get_config().arp_proxy.mode, ProxyMode::Denylist);
+        let req_body = br#"{ "arp_proxy": { "mode": "blocklist" } }"#.to_vec();
+        let (status, _) = request_oneshot_json(app.clone(), Method::PATCH, "/", req_body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// ルーターのパスの `:mac_address` 等を例の値に置き換える
+    fn example_uri(path: &str) -> String {
+        path.split('/')
+            .map(|segment| match segment {
+                ":mac_address" => "02:00:00:00:0f:02",
+                ":target_ip" => "192.168.0.10",
+                segment => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn every_route_is_documented() {
+        let (healer, _heal_requests) = HealRequester::new();
+        let app = Router::new().nest(
+            "/api",
+            create_router(
+                Arc::new(create_dummy_config_repo()),
+                Arc::new(create_dummy_allowedmac_repo()),
+                Arc::new(create_dummy_arplog_repo()),
+                Arc::new(create_dummy_host_repo()),
+                Arc::new(EventRepositoryForMemory::new()),
+                Arc::new(DeniedMacRepositoryForMemory::new()),
+                healer,
+            ),
+        );
+        let spec = serde_json::to_value(openapi()).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+        assert!(!paths.is_empty());
+        let methods = [
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ];
+        // RouterはパスをDebug出力でしか公開しないため、ハンドラの一覧から作られたドキュメントを基準にする
+        for (spec_path, item) in paths {
+            let path = spec_path.replace('{', ":").replace('}', "");
+            for method in methods.iter() {
+                let (status, body) =
+                    request_oneshot_json(app.clone(), method.clone(), &example_uri(&path), vec![])
+                        .await;
+                if item[method.as_str().to_lowercase()].is_null() {
+                    // ドキュメントに無いメソッドはルーターにも存在しない
+                    assert_eq!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "{} {} is missing from the OpenAPI document",
+                        method,
+                        spec_path
+                    );
+                    continue;
+                }
+                // ハンドラが返す404にはproblem+jsonのボディがある
+                if status == StatusCode::NOT_FOUND {
+                    assert!(
+                        serde_json::from_slice::<ProblemDetails>(&body).is_ok(),
+                        "{} {} is not routed",
+                        method,
+                        spec_path
+                    );
+                }
+                assert_ne!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {}",
+                    method,
+                    spec_path
+                );
+            }
+        }
     }
 }
@@ -1,26 +1,532 @@
-use std::str::FromStr;
+use std::{
+    net::Ipv4Addr,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
-use pnet::util::MacAddr;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use ipnetwork::Ipv4Network;
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use validator::{Validate, ValidationError};
 
-fn validate_mac_address(text: &str) -> Result<(), ValidationError> {
-    match MacAddr::from_str(text) {
+use crate::{
+    config::{ArpProxyConfig, Config, ProxyMode},
+    repositories::{
+        allowed_mac::{
+            AllowSchedule, AllowedMac, AllowedMacPatch, BulkOperation, BulkOutcome, BulkResult,
+            MacPrefix,
+        },
+        arplog::ArpLog,
+        denied_mac::DeniedMac,
+        event::{Event, EventKind},
+        host::Host,
+    },
+};
+
+/// MACアドレスか、`00:1b:63:*` や `02:00:00:00:00:00/40` の形式のプレフィックス
+fn validate_mac_prefix(text: &str) -> Result<(), ValidationError> {
+    match MacPrefix::from_str(text) {
         Ok(_) => Ok(()),
-        Err(_) => Err(ValidationError::new("Invalid MAC address")),
+        Err(_) => Err(ValidationError::new("invalid_mac_address")
+            .with_message("Invalid MAC address or prefix".into())),
+    }
+}
+
+fn validate_expires_at(expires_at: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *expires_at > Utc::now() {
+        Ok(())
+    } else {
+        Err(ValidationError::new("expired").with_message("Must be in the future".into()))
+    }
+}
+
+fn validate_expiry(expires_at_specified: bool, ttl: Option<u32>) -> Result<(), ValidationError> {
+    if expires_at_specified && ttl.is_some() {
+        Err(ValidationError::new("conflicting_expiry")
+            .with_message("Only one of expires_at and ttl can be specified".into()))
+    } else {
+        Ok(())
+    }
+}
+
+/// `expires_at` か、現在時刻に `ttl` 秒を加えた時刻を有効期限とする
+fn expiry(expires_at: Option<DateTime<Utc>>, ttl: Option<u32>) -> Option<SystemTime> {
+    expires_at
+        .map(SystemTime::from)
+        .or_else(|| ttl.map(|ttl| SystemTime::now() + Duration::from_secs(ttl.into())))
+}
+
+/// 省略された項目 (None) とnull (Some(None)) を区別する
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// 許可する曜日と時間帯。`end` が `start` より前の場合は日付をまたぐ
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = "validate_schedule"))]
+pub(super) struct AllowScheduleSchema {
+    /// 省略した場合は毎日
+    #[serde(default)]
+    #[schema(value_type = Vec<String>, example = json!(["mon", "tue", "wed", "thu", "fri"]))]
+    pub weekdays: Vec<Weekday>,
+    #[schema(value_type = String, example = "08:30")]
+    pub start: NaiveTime,
+    #[schema(value_type = String, example = "16:00")]
+    pub end: NaiveTime,
+}
+
+fn validate_schedule(schedule: &AllowScheduleSchema) -> Result<(), ValidationError> {
+    if schedule.start == schedule.end {
+        Err(ValidationError::new("empty_schedule")
+            .with_message("start and end must be different".into()))
+    } else {
+        Ok(())
+    }
+}
+
+impl From<AllowSchedule> for AllowScheduleSchema {
+    fn from(schedule: AllowSchedule) -> Self {
+        Self {
+            weekdays: schedule.weekdays,
+            start: schedule.start,
+            end: schedule.end,
+        }
     }
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq)]
+impl From<AllowScheduleSchema> for AllowSchedule {
+    fn from(schedule: AllowScheduleSchema) -> Self {
+        Self {
+            weekdays: schedule.weekdays,
+            start: schedule.start,
+            end: schedule.end,
+        }
+    }
+}
+
+/// 許可されたMACアドレスと付帯情報。作成日時などはサーバが設定し、リクエストでは無視する
+///
+/// `expires_at` か `ttl` (秒) を指定すると、期限を過ぎた時点で許可を取り消す。
+/// `schedules` を指定すると、設定ファイルの `timezone` でいずれかの時間帯に含まれる間だけ許可する。
+/// `allowed_ips` を指定すると、それ以外の送信元IPアドレスを使用した場合は遮断する
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq, Default, ToSchema)]
+#[validate(schema(function = "validate_allowedmac_expiry"))]
 pub(super) struct AllowedMacSchema {
-    #[validate(custom(function = "validate_mac_address"))]
+    #[schema(example = "02:00:00:00:00:01")]
+    #[validate(custom(function = "validate_mac_prefix"))]
     pub mac_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = 256))]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = 256))]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(max = 4096))]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub created_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(read_only)]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom(function = "validate_expires_at"))]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing)]
+    #[schema(write_only, minimum = 1)]
+    #[validate(range(min = 1))]
+    pub ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[validate(length(max = 64), nested)]
+    pub schedules: Vec<AllowScheduleSchema>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<String>, example = json!(["192.168.0.10", "10.0.1.0/24"]))]
+    #[validate(length(max = 64))]
+    pub allowed_ips: Vec<Ipv4Network>,
+}
+
+fn validate_allowedmac_expiry(schema: &AllowedMacSchema) -> Result<(), ValidationError> {
+    validate_expiry(schema.expires_at.is_some(), schema.ttl)
+}
+
+impl From<AllowedMac> for AllowedMacSchema {
+    fn from(entry: AllowedMac) -> Self {
+        Self {
+            mac_address: entry.address.to_string(),
+            label: entry.label,
+            owner: entry.owner,
+            note: entry.note,
+            created_at: entry.created_at.map(DateTime::from),
+            created_by: entry.created_by,
+            updated_at: entry.updated_at.map(DateTime::from),
+            expires_at: entry.expires_at.map(DateTime::from),
+            ttl: None,
+            schedules: entry
+                .schedules
+                .into_iter()
+                .map(AllowScheduleSchema::from)
+                .collect(),
+            allowed_ips: entry.allowed_ips,
+        }
+    }
+}
+
+impl AllowedMacSchema {
+    /// リクエストから新しいエントリを作成する
+    pub fn to_entry(&self, address: MacPrefix, created_by: Option<String>) -> AllowedMac {
+        AllowedMac {
+            label: self.label.clone().filter(|label| !label.is_empty()),
+            owner: self.owner.clone().filter(|owner| !owner.is_empty()),
+            note: self.note.clone().filter(|note| !note.is_empty()),
+            created_by,
+            expires_at: expiry(self.expires_at, self.ttl),
+            schedules: self
+                .schedules
+                .iter()
+                .cloned()
+                .map(AllowSchedule::from)
+                .collect(),
+            allowed_ips: self.allowed_ips.clone(),
+            ..AllowedMac::new(address)
+        }
+    }
+}
+
+/// PUT, PATCH /v1/allowed-macs/{mac_address} のリクエスト。空文字列の項目は削除する
+///
+/// `expires_at` にnullを指定すると無期限に、`schedules` に空の配列を指定すると常に許可する。
+/// `allowed_ips` に空の配列を指定すると送信元IPアドレスを制限しない
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = "validate_metadata_expiry"))]
+pub(super) struct AllowedMacMetadataSchema {
+    #[validate(length(max = 256))]
+    pub label: Option<String>,
+    #[validate(length(max = 256))]
+    pub owner: Option<String>,
+    #[validate(length(max = 4096))]
+    pub note: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    #[schema(value_type = Option<String>, format = DateTime, nullable)]
+    #[validate(custom(function = "validate_expires_at"))]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[schema(minimum = 1)]
+    #[validate(range(min = 1))]
+    pub ttl: Option<u32>,
+    #[validate(length(max = 64), nested)]
+    pub schedules: Option<Vec<AllowScheduleSchema>>,
+    #[schema(value_type = Option<Vec<String>>, example = json!(["192.168.0.10"]))]
+    #[validate(length(max = 64))]
+    pub allowed_ips: Option<Vec<Ipv4Network>>,
+}
+
+fn validate_metadata_expiry(schema: &AllowedMacMetadataSchema) -> Result<(), ValidationError> {
+    validate_expiry(schema.expires_at.is_some(), schema.ttl)
+}
+
+impl From<AllowedMacMetadataSchema> for AllowedMacPatch {
+    fn from(metadata: AllowedMacMetadataSchema) -> Self {
+        let expires_at = match (metadata.expires_at, metadata.ttl) {
+            (Some(expires_at), _) => Some(expires_at.map(SystemTime::from)),
+            (None, Some(ttl)) => Some(expiry(None, Some(ttl))),
+            (None, None) => None,
+        };
+        Self {
+            label: metadata.label,
+            owner: metadata.owner,
+            note: metadata.note,
+            expires_at,
+            schedules: metadata
+                .schedules
+                .map(|schedules| schedules.into_iter().map(AllowSchedule::from).collect()),
+            allowed_ips: metadata.allowed_ips,
+        }
+    }
 }
 
 pub(super) type AllowedMacPostResponseSchema = AllowedMacSchema;
 pub(super) type AllowedMacPostSchema = AllowedMacSchema;
 pub(super) type AllowedMacDeleteSchema = AllowedMacSchema;
 
+/// 一括操作の種類。replaceは指定したもの以外の許可を取り消す
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum BulkOperationSchema {
+    Add,
+    Remove,
+    Replace,
+}
+
+impl From<BulkOperationSchema> for BulkOperation {
+    fn from(operation: BulkOperationSchema) -> Self {
+        match operation {
+            BulkOperationSchema::Add => BulkOperation::Add,
+            BulkOperationSchema::Remove => BulkOperation::Remove,
+            BulkOperationSchema::Replace => BulkOperation::Replace,
+        }
+    }
+}
+
+/// POST /v1/allowed-macs/bulk のリクエスト。全ての項目を検証してから反映する
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct AllowedMacBulkSchema {
+    pub operation: BulkOperationSchema,
+    #[validate(length(max = 4096), nested)]
+    pub entries: Vec<AllowedMacSchema>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum BulkOutcomeSchema {
+    Added,
+    Removed,
+    Unchanged,
+    NotFound,
+}
+
+impl From<BulkOutcome> for BulkOutcomeSchema {
+    fn from(outcome: BulkOutcome) -> Self {
+        match outcome {
+            BulkOutcome::Added => BulkOutcomeSchema::Added,
+            BulkOutcome::Removed => BulkOutcomeSchema::Removed,
+            BulkOutcome::Unchanged => BulkOutcomeSchema::Unchanged,
+            BulkOutcome::NotFound => BulkOutcomeSchema::NotFound,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct AllowedMacBulkEntryResultSchema {
+    pub mac_address: String,
+    pub result: BulkOutcomeSchema,
+}
+
+/// POST /v1/allowed-macs/bulk のレスポンス。`results` はリクエストの `entries` と同じ順になる
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct AllowedMacBulkResponseSchema {
+    pub operation: BulkOperationSchema,
+    pub results: Vec<AllowedMacBulkEntryResultSchema>,
+    /// replaceで指定されなかったために許可を取り消したMACアドレス
+    pub removed: Vec<String>,
+}
+
+impl AllowedMacBulkResponseSchema {
+    pub fn new(operation: BulkOperationSchema, result: BulkResult) -> Self {
+        Self {
+            operation,
+            results: result
+                .outcomes
+                .into_iter()
+                .map(|(addr, outcome)| AllowedMacBulkEntryResultSchema {
+                    mac_address: addr.to_string(),
+                    result: outcome.into(),
+                })
+                .collect(),
+            removed: result.removed.iter().map(|addr| addr.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct HostSchema {
+    pub mac_address: String,
+    #[schema(value_type = String, format = Ipv4)]
+    pub ip_address: Ipv4Addr,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub allowed: bool,
+}
+
+impl From<Host> for HostSchema {
+    fn from(host: Host) -> Self {
+        Self {
+            mac_address: host.mac.to_string(),
+            ip_address: host.ip,
+            first_seen: host.first_seen.into(),
+            last_seen: host.last_seen.into(),
+            allowed: host.allowed,
+        }
+    }
+}
+
+/// GET /hosts の絞り込み条件
+#[derive(Debug, Deserialize, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct HostQuerySchema {
+    pub mac_address: Option<String>,
+    #[param(value_type = Option<String>, format = Ipv4)]
+    pub ip_address: Option<Ipv4Addr>,
+}
+
+/// 拒否リストのMACアドレス (またはプレフィックス) と付帯情報
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct DeniedMacSchema {
+    #[schema(example = "02:00:00:00:00:01")]
+    pub mac_address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+}
+
+impl From<DeniedMac> for DeniedMacSchema {
+    fn from(entry: DeniedMac) -> Self {
+        Self {
+            mac_address: entry.address.to_string(),
+            label: entry.label,
+            note: entry.note,
+            created_at: entry.created_at.map(DateTime::from),
+            created_by: entry.created_by,
+        }
+    }
+}
+
+/// PUT /v1/denied-macs/{mac_address} のリクエスト
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, PartialEq, Eq, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct DeniedMacMetadataSchema {
+    #[validate(length(max = 256))]
+    pub label: Option<String>,
+    #[validate(length(max = 4096))]
+    pub note: Option<String>,
+}
+
+impl DeniedMacMetadataSchema {
+    /// リクエストから新しいエントリを作成する。空文字列の項目は記録しない
+    pub fn to_entry(&self, address: MacPrefix, created_by: Option<String>) -> DeniedMac {
+        DeniedMac {
+            label: self.label.clone().filter(|label| !label.is_empty()),
+            note: self.note.clone().filter(|note| !note.is_empty()),
+            created_by,
+            ..DeniedMac::new(address)
+        }
+    }
+}
+
+/// 許可されたMACアドレスについて検知した事象
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct EventSchema {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub kind: EventKind,
+    pub mac_address: String,
+    #[schema(value_type = Vec<String>)]
+    pub ip_addresses: Vec<Ipv4Addr>,
+    /// 自動で許可を取り消したか
+    pub revoked: bool,
+}
+
+impl From<Event> for EventSchema {
+    fn from(event: Event) -> Self {
+        Self {
+            id: event.id,
+            time: event.time.into(),
+            kind: event.kind,
+            mac_address: event.mac.to_string(),
+            ip_addresses: event.ips,
+            revoked: event.revoked,
+        }
+    }
+}
+
+/// GET /events の絞り込み条件
+#[derive(Debug, Deserialize, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct EventQuerySchema {
+    pub mac_address: Option<String>,
+    pub kind: Option<EventKind>,
+}
+
+/// 送信元MACアドレス毎にまとめたArpLog
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct ArpLogSchema {
+    pub sender_mac: String,
+    #[schema(value_type = String, format = Ipv4)]
+    pub sender_ip: Ipv4Addr,
+    pub targets: Vec<ArpLogTargetSchema>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct ArpLogTargetSchema {
+    #[schema(value_type = String, format = Ipv4)]
+    pub target_ip: Ipv4Addr,
+    pub last_seen: DateTime<Utc>,
+}
+
+impl From<&ArpLog> for ArpLogTargetSchema {
+    fn from(arplog: &ArpLog) -> Self {
+        Self {
+            target_ip: arplog.target_ip,
+            last_seen: arplog.last_seen.into(),
+        }
+    }
+}
+
+/// 実行中に変更できる設定
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub(super) struct ConfigSchema {
+    pub arp_proxy: ArpProxyConfig,
+}
+
+impl From<Config> for ConfigSchema {
+    fn from(config: Config) -> Self {
+        Self {
+            arp_proxy: config.arp_proxy,
+        }
+    }
+}
+
+/// PATCH /config のリクエスト。指定した項目のみを変更する
+#[derive(Debug, Validate, Serialize, Deserialize, Clone, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ConfigPatchSchema {
+    #[serde(default)]
+    pub arp_proxy: Option<ArpProxyPatchSchema>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ArpProxyPatchSchema {
+    pub mode: Option<ProxyMode>,
+    pub proxy_allowed_macs: Option<bool>,
+    pub arp_reply_interval: Option<u32>,
+    pub arp_reply_duration: Option<u32>,
+}
+
+impl ConfigPatchSchema {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(patch) = self.arp_proxy.as_ref() {
+            if let Some(value) = patch.mode {
+                config.arp_proxy.mode = value;
+            }
+            if let Some(value) = patch.proxy_allowed_macs {
+                config.arp_proxy.proxy_allowed_macs = value;
+            }
+            if let Some(value) = patch.arp_reply_interval {
+                config.arp_proxy.arp_reply_interval = value;
+            }
+            if let Some(value) = patch.arp_reply_duration {
+                config.arp_proxy.arp_reply_duration = value;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -35,12 +541,15 @@ mod test {
         let mut samples = Vec::new();
         samples.push(AllowedMacSchema {
             mac_address: "02:ab:cd:ef:09:00".to_string(),
+            ..Default::default()
         });
         samples.push(AllowedMacSchema {
             mac_address: "02:AB:CD:EF:09:00".to_string(),
+            ..Default::default()
         });
         samples.push(AllowedMacSchema {
             mac_address: "02:Ab:cD:Ef:09:00".to_string(),
+            ..Default::default()
         });
         for sample in samples.iter() {
             sample.validate().expect("Validation Error");
@@ -56,15 +565,19 @@ mod test {
         let mut samples = Vec::new();
         samples.push(AllowedMacSchema {
             mac_address: "02-ab-cd-ef-09-00".to_string(),
+            ..Default::default()
         });
         samples.push(AllowedMacSchema {
             mac_address: "02-ab:cd-ef:09-00".to_string(),
+            ..Default::default()
         });
         samples.push(AllowedMacSchema {
             mac_address: "02:Ab:cD:Ef:09:00:01:13".to_string(),
+            ..Default::default()
         });
         samples.push(AllowedMacSchema {
             mac_address: "hello, world".to_string(),
+            ..Default::default()
         });
         for sample in samples.iter() {
             sample
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use super::api::error::ApiError;
+use crate::config::{AuthConfig, Role};
+
+/// 認証されたAPIの利用者。ハンドラからはExtensionとして参照できる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+/// TLS接続で検証されたクライアント証明書。接続を受け付けた時点でリクエストのExtensionに追加される
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub common_name: Option<String>,
+}
+
+/// 設定されたAPIトークンのハッシュやクライアント証明書と照合する
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    tokens: Vec<(String, Role, [u8; 32])>,
+    client_certificates: Vec<(String, Role)>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Self, anyhow::Error> {
+        let mut tokens = Vec::new();
+        for token in config.tokens.iter() {
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(token.sha256.trim(), &mut hash).map_err(|_| {
+                anyhow::anyhow!(
+                    "Token {:?} does not have a valid SHA-256 hash (64 hex digits)",
+                    token.name
+                )
+            })?;
+            tokens.push((token.name.clone(), token.role, hash));
+        }
+        let client_certificates = config
+            .client_certificates
+            .iter()
+            .map(|cert| (cert.common_name.clone(), cert.role))
+            .collect();
+        Ok(Self {
+            tokens,
+            client_certificates,
+        })
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<Principal> {
+        let hash: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        // 一致したかどうかで処理時間が変わらないよう全てのトークンと比較する
+        let mut found = None;
+        for (name, role, expected) in self.tokens.iter() {
+            if constant_time_eq(&hash, expected) && found.is_none() {
+                found = Some(Principal {
+                    name: name.clone(),
+                    role: *role,
+                });
+            }
+        }
+        found
+    }
+
+    pub fn authenticate_certificate(&self, cert: &ClientCertificate) -> Option<Principal> {
+        let common_name = cert.common_name.as_ref()?;
+        self.client_certificates
+            .iter()
+            .find(|(name, _)| name == common_name)
+            .map(|(name, role)| Principal {
+                name: name.clone(),
+                role: *role,
+            })
+    }
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+fn bearer_token(req: &Request) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("Bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// クライアント証明書または `Authorization: Bearer` ヘッダを検証するミドルウェア
+///
+/// 設定に対応するクライアント証明書があればそれを優先し、なければAPIトークンで認証する
+pub async fn require_authentication(
+    State(authenticator): State<Arc<Authenticator>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let principal = req
+        .extensions()
+        .get::<ClientCertificate>()
+        .and_then(|cert| authenticator.authenticate_certificate(cert))
+        .or_else(|| bearer_token(&req).and_then(|token| authenticator.authenticate(token)));
+    match principal {
+        Some(principal) => {
+            debug!(
+                "Authenticated as {:?} ({:?}): {} {}",
+                principal.name,
+                principal.role,
+                req.method(),
+                req.uri()
+            );
+            req.extensions_mut().insert(principal);
+            next.run(req).await
+        }
+        None => {
+            warn!(
+                "Unauthenticated request rejected: {} {}",
+                req.method(),
+                req.uri()
+            );
+            (
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                ApiError::new(StatusCode::UNAUTHORIZED, "Authentication required"),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// ルートに必要な権限を検証するミドルウェア。`route_layer` で各ルートに設定する
+///
+/// 認証が無効 (Principalが存在しない) の場合は全て許可する
+pub async fn require_role(State(required): State<Role>, req: Request, next: Next) -> Response {
+    match req.extensions().get::<Principal>() {
+        Some(principal) if principal.role < required => {
+            warn!(
+                "Forbidden request by {:?} ({:?}, {:?} required): {} {}",
+                principal.name,
+                principal.role,
+                required,
+                req.method(),
+                req.uri()
+            );
+            ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("Insufficient role ({:?} required)", required),
+            )
+            .into_response()
+        }
+        _ => next.run(req).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        middleware,
+        routing::get,
+        Extension, Router,
+    };
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt;
+
+    use super::{
+        require_authentication, require_role, Authenticator, ClientCertificate, Principal,
+    };
+    use crate::config::{ApiTokenConfig, AuthConfig, ClientCertificateConfig, Role};
+
+    fn create_dummy_authenticator() -> Authenticator {
+        let config = AuthConfig {
+            tokens: vec![
+                ApiTokenConfig {
+                    name: "ops".to_string(),
+                    sha256: hex::encode(Sha256::digest(b"secret-token")),
+                    role: Role::Operator,
+                },
+                ApiTokenConfig {
+                    name: "noc".to_string(),
+                    sha256: hex::encode(Sha256::digest(b"viewer-token")),
+                    role: Role::Viewer,
+                },
+            ],
+            client_certificates: vec![ClientCertificateConfig {
+                common_name: "automation".to_string(),
+                role: Role::Operator,
+            }],
+        };
+        Authenticator::new(&config).unwrap()
+    }
+
+    fn create_dummy_router() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(principal): Extension<Principal>| async move { principal.name }),
+            )
+            .route(
+                "/operator",
+                get(|| async { "ok" })
+                    .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+            )
+            .layer(middleware::from_fn_with_state(
+                Arc::new(create_dummy_authenticator()),
+                require_authentication,
+            ))
+    }
+
+    async fn request_with_authorization(
+        app: Router,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> StatusCode {
+        let mut req = Request::builder().uri(path);
+        if let Some(value) = authorization {
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+        res.status()
+    }
+
+    #[test]
+    fn authenticate_token() {
+        let authenticator = create_dummy_authenticator();
+        assert_eq!(
+            authenticator.authenticate("secret-token"),
+            Some(Principal {
+                name: "ops".to_string(),
+                role: Role::Operator,
+            })
+        );
+        assert_eq!(authenticator.authenticate("secret-token2"), None);
+        assert_eq!(authenticator.authenticate(""), None);
+    }
+
+    #[test]
+    fn reject_invalid_hash() {
+        let config = AuthConfig {
+            tokens: vec![ApiTokenConfig {
+                name: "ops".to_string(),
+                sha256: "secret-token".to_string(),
+                role: Role::Admin,
+            }],
+            client_certificates: vec![],
+        };
+        assert!(Authenticator::new(&config).is_err());
+    }
+
+    #[test]
+    fn authenticate_client_certificate() {
+        let authenticator = create_dummy_authenticator();
+        let cert = |name: Option<&str>| ClientCertificate {
+            common_name: name.map(str::to_string),
+        };
+        assert_eq!(
+            authenticator.authenticate_certificate(&cert(Some("automation"))),
+            Some(Principal {
+                name: "automation".to_string(),
+                role: Role::Operator,
+            })
+        );
+        assert_eq!(
+            authenticator.authenticate_certificate(&cert(Some("ops"))),
+            None
+        );
+        assert_eq!(authenticator.authenticate_certificate(&cert(None)), None);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_require_bearer_token() {
+        let app = create_dummy_router();
+        let cases = [
+            (Some("Bearer secret-token"), StatusCode::OK),
+            (Some("bearer secret-token"), StatusCode::OK),
+            (Some("Bearer wrong-token"), StatusCode::UNAUTHORIZED),
+            (Some("Basic secret-token"), StatusCode::UNAUTHORIZED),
+            (Some("secret-token"), StatusCode::UNAUTHORIZED),
+            (None, StatusCode::UNAUTHORIZED),
+        ];
+        for (authorization, expected) in cases {
+            let status = request_with_authorization(app.clone(), "/", authorization).await;
+            assert_eq!(status, expected, "{:?}", authorization);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_accept_client_certificate() {
+        let app = create_dummy_router();
+        let cases = [
+            (Some("automation"), StatusCode::OK),
+            (Some("unknown"), StatusCode::UNAUTHORIZED),
+            (None, StatusCode::UNAUTHORIZED),
+        ];
+        for (common_name, expected) in cases {
+            let mut req = Request::builder()
+                .uri("/operator")
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut().insert(ClientCertificate {
+                common_name: common_name.map(str::to_string),
+            });
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), expected, "{:?}", common_name);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn should_require_role() {
+        let app = create_dummy_router();
+        let cases = [
+            (Some("Bearer secret-token"), StatusCode::OK),
+            (Some("Bearer viewer-token"), StatusCode::FORBIDDEN),
+            (None, StatusCode::UNAUTHORIZED),
+        ];
+        for (authorization, expected) in cases {
+            let status = request_with_authorization(app.clone(), "/operator", authorization).await;
+            assert_eq!(status, expected, "{:?}", authorization);
+        }
+        // 認証が無効な場合は許可される
+        let app = Router::new().route(
+            "/operator",
+            get(|| async { "ok" })
+                .route_layer(middleware::from_fn_with_state(Role::Operator, require_role)),
+        );
+        let status = request_with_authorization(app, "/operator", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
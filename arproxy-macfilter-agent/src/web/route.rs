@@ -1,25 +1,134 @@
 use std::sync::Arc;
 
-use crate::repositories::{
-    allowed_mac::AllowedMacRepository, arplog::ArpLogRepository, config::ConfigRepository,
+use crate::{
+    networks::healer::HealRequester,
+    repositories::{
+        allowed_mac::AllowedMacRepository, arplog::ArpLogRepository, config::ConfigRepository,
+        denied_mac::DeniedMacRepository, event::EventRepository, host::HostRepository,
+    },
 };
-use axum::Router;
+use axum::{middleware, Router};
 
-use super::api;
+use super::{api, auth};
 
-pub fn create_router<C, M, A>(
+#[allow(clippy::too_many_arguments)]
+pub fn create_router<C, M, A, H, E, D>(
     config_repo: Arc<C>,
     allowedmac_repo: Arc<M>,
     arplog_repo: Arc<A>,
+    host_repo: Arc<H>,
+    event_repo: Arc<E>,
+    deniedmac_repo: Arc<D>,
+    healer: HealRequester,
+    authenticator: Option<auth::Authenticator>,
 ) -> Router
 where
     C: ConfigRepository,
     M: AllowedMacRepository,
     A: ArpLogRepository,
+    H: HostRepository,
+    E: EventRepository,
+    D: DeniedMacRepository,
 {
+    // APIの構成が分かるため、OpenAPIドキュメントも認証の対象とする
     let app = Router::new().nest(
         "/api",
-        api::route::create_router(config_repo, allowedmac_repo, arplog_repo),
+        api::route::create_router(
+            config_repo,
+            allowedmac_repo,
+            arplog_repo,
+            host_repo,
+            event_repo,
+            deniedmac_repo,
+            healer,
+        ),
     );
-    app
+    let app = app.merge(api::openapi::create_router());
+    match authenticator {
+        Some(authenticator) => app.layer(middleware::from_fn_with_state(
+            Arc::new(authenticator),
+            auth::require_authentication,
+        )),
+        None => app,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt;
+
+    use super::create_router;
+    use crate::{
+        config::{ApiTokenConfig, AuthConfig, Role},
+        networks::healer::HealRequester,
+        repositories::{
+            allowed_mac::AllowedMacRepositoryForMemory, arplog::ArpLogRepositoryForMemory,
+            config::ConfigRepositoryForMemory, denied_mac::DeniedMacRepositoryForMemory,
+            event::EventRepositoryForMemory, host::HostRepositoryForMemory,
+        },
+        web::auth::Authenticator,
+    };
+
+    fn create_dummy_router() -> Router {
+        let config = serde_json::from_value(serde_json::json!({
+            "interface": "eth0",
+            "arp_proxy": {
+                "proxy_allowed_macs": false,
+                "arp_reply_interval": 5,
+                "arp_reply_duration": 60
+            },
+            "administration": {
+                "enable_api": true,
+                "listen_address": "127.0.0.1",
+                "listen_port": 3000
+            }
+        }))
+        .unwrap();
+        let authenticator = Authenticator::new(&AuthConfig {
+            tokens: vec![ApiTokenConfig {
+                name: "noc".to_string(),
+                sha256: hex::encode(Sha256::digest(b"viewer-token")),
+                role: Role::Viewer,
+            }],
+            client_certificates: vec![],
+        })
+        .unwrap();
+        let (healer, _heal_requests) = HealRequester::new();
+        create_router(
+            Arc::new(ConfigRepositoryForMemory::new(config)),
+            Arc::new(AllowedMacRepositoryForMemory::new()),
+            Arc::new(ArpLogRepositoryForMemory::new()),
+            Arc::new(HostRepositoryForMemory::new()),
+            Arc::new(EventRepositoryForMemory::new()),
+            Arc::new(DeniedMacRepositoryForMemory::new()),
+            healer,
+            Some(authenticator),
+        )
+    }
+
+    #[tokio::test]
+    async fn openapi_requires_authentication() {
+        let app = create_dummy_router();
+        let req = Request::builder()
+            .uri("/api/openapi.json")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let req = Request::builder()
+            .uri("/api/openapi.json")
+            .header(header::AUTHORIZATION, "Bearer viewer-token")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
 }
@@ -0,0 +1,448 @@
+use std::{
+    fs::{self, Permissions},
+    future::Future,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::Router;
+use hyper::{body::Incoming, service::Service, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{
+        conn::auto,
+        graceful::{GracefulShutdown, Watcher},
+    },
+    service::TowerToHyperService,
+};
+use nix::{
+    sys::stat::{umask, Mode},
+    unistd::{chown, Group, User},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tracing::{debug, warn};
+
+use super::{auth::ClientCertificate, tls::client_certificate};
+use crate::config::UnixSocketConfig;
+
+/// TLSハンドシェイクを待つ最大時間
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 終了時に処理中のリクエストを待つ最大時間
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLSで管理用APIを提供する。`signal` が完了すると新しい接続の受け付けを止め、処理中の接続を待って返る
+///
+/// クライアント証明書が提示された場合は `ClientCertificate` としてリクエストのExtensionに追加する
+pub async fn serve_tls<F>(
+    listener: TcpListener,
+    tls_config: Arc<ServerConfig>,
+    app: Router,
+    signal: F,
+) -> Result<(), anyhow::Error>
+where
+    F: Future<Output = ()>,
+{
+    let acceptor = TlsAcceptor::from(tls_config);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake with {} timed out", peer);
+                        return;
+                    }
+                };
+            let client_cert = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(client_certificate);
+            if let Some(cert) = client_cert.as_ref() {
+                debug!("Client certificate from {}: {:?}", peer, cert.common_name);
+            }
+            serve_connection(stream, app, client_cert, watcher, peer.to_string()).await;
+        });
+    }
+    drop(listener);
+    drain(graceful).await;
+    Ok(())
+}
+
+/// Unixドメインソケットを作成し、パーミッションと所有者を設定する
+///
+/// 既存のソケットファイルは、接続を受け付けるプロセスがいなければ削除して作り直す
+pub fn bind_unix(config: &UnixSocketConfig) -> Result<UnixListener, anyhow::Error> {
+    let path = &config.path;
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{:?} exists and is not a socket", path);
+        }
+        if UnixStream::connect(path).is_ok() {
+            anyhow::bail!("{:?} is already in use", path);
+        }
+        debug!("Removing stale socket {:?}", path);
+        fs::remove_file(path)?;
+    }
+    let mode = u32::from_str_radix(config.mode.trim_start_matches("0o"), 8)
+        .with_context(|| format!("Invalid socket mode {:?}", config.mode))?;
+    let uid = match config.owner.as_deref() {
+        Some(name) => Some(
+            User::from_name(name)?
+                .ok_or_else(|| anyhow::anyhow!("User {:?} not found", name))?
+                .uid,
+        ),
+        None => None,
+    };
+    let gid = match config.group.as_deref() {
+        Some(name) => Some(
+            Group::from_name(name)?
+                .ok_or_else(|| anyhow::anyhow!("Group {:?} not found", name))?
+                .gid,
+        ),
+        None => None,
+    };
+    // ソケットはumaskに従った権限で作成されるため、指定の権限に変えるまでは所有者以外が接続できないようにする
+    let previous = umask(Mode::from_bits_truncate(0o177));
+    let bound = UnixListener::bind(path);
+    umask(previous);
+    let listener = bound.with_context(|| format!("Failed to bind {:?}", path))?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    if uid.is_some() || gid.is_some() {
+        chown(path, uid, gid).with_context(|| format!("Failed to change owner of {:?}", path))?;
+    }
+    Ok(listener)
+}
+
+/// Unixドメインソケットで管理用APIを提供する。終了時にソケットファイルを削除する
+pub async fn serve_unix<F>(
+    listener: UnixListener,
+    app: Router,
+    signal: F,
+) -> Result<(), anyhow::Error>
+where
+    F: Future<Output = ()>,
+{
+    let path = listener
+        .local_addr()?
+        .as_pathname()
+        .map(|path| path.to_path_buf());
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+        let peer = match stream.peer_cred() {
+            Ok(cred) => format!("unix socket (uid {})", cred.uid()),
+            Err(_) => "unix socket".to_string(),
+        };
+        tokio::spawn(serve_connection(
+            stream,
+            app.clone(),
+            None,
+            graceful.watcher(),
+            peer,
+        ));
+    }
+    drop(listener);
+    if let Some(path) = path {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Failed to remove {:?}: {}", path, e);
+        }
+    }
+    drain(graceful).await;
+    Ok(())
+}
+
+/// 1つの接続でHTTP/1.1またはHTTP/2を提供する
+async fn serve_connection<I>(
+    io: I,
+    app: Router,
+    client_cert: Option<ClientCertificate>,
+    watcher: Watcher,
+    peer: String,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = TowerToHyperService::new(app);
+    let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+        if let Some(cert) = client_cert.clone() {
+            req.extensions_mut().insert(cert);
+        }
+        app.call(req)
+    });
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    if let Err(e) = watcher.watch(conn.into_owned()).await {
+        debug!("Connection from {} closed with error: {}", peer, e);
+    }
+}
+
+/// 処理中の接続が終わるまで待つ
+async fn drain(graceful: GracefulShutdown) {
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("Timed out waiting for API connections to close");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        net::{Ipv4Addr, SocketAddr},
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        sync::Arc,
+    };
+
+    use axum::{routing::get, Extension, Router};
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream, UnixStream},
+        sync::oneshot,
+        task::JoinHandle,
+    };
+    use tokio_rustls::{
+        rustls::{
+            self,
+            crypto::ring,
+            pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+            ServerConfig,
+        },
+        TlsConnector,
+    };
+
+    use super::{bind_unix, serve_tls, serve_unix};
+    use crate::{
+        config::{TlsConfig, UnixSocketConfig},
+        web::{auth::ClientCertificate, tls::load_server_config},
+    };
+
+    struct TestServer {
+        addr: SocketAddr,
+        stop: oneshot::Sender<()>,
+        handle: JoinHandle<Result<(), anyhow::Error>>,
+    }
+
+    impl TestServer {
+        async fn start(server_config: Arc<ServerConfig>) -> Self {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = Router::new().route(
+                "/",
+                get(|cert: Option<Extension<ClientCertificate>>| async move {
+                    match cert {
+                        Some(Extension(cert)) => cert.common_name.unwrap_or_default(),
+                        None => "hello".to_string(),
+                    }
+                }),
+            );
+            let (stop, stopped) = oneshot::channel::<()>();
+            let handle = tokio::spawn(serve_tls(listener, server_config, app, async {
+                let _ = stopped.await;
+            }));
+            Self { addr, stop, handle }
+        }
+
+        async fn stop(self) {
+            self.stop.send(()).unwrap();
+            self.handle.await.unwrap().unwrap();
+        }
+    }
+
+    fn client_builder(
+        server_cert: &Path,
+    ) -> rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsClientCert> {
+        let mut roots = rustls::RootCertStore::empty();
+        let pem = std::fs::read(server_cert).unwrap();
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+    }
+
+    async fn request_over_tls(
+        addr: SocketAddr,
+        client_config: rustls::ClientConfig,
+    ) -> Result<String, io::Error> {
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    fn self_signed_config(dir: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            generate_self_signed: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_serve_over_tls() {
+        let dir = tempdir().unwrap();
+        let config = self_signed_config(dir.path());
+        let server_config = load_server_config(&config, Ipv4Addr::LOCALHOST, None).unwrap();
+        let server = TestServer::start(server_config).await;
+
+        // 生成された証明書を信頼するクライアントで接続する
+        let client_config = client_builder(&config.cert_path).with_no_client_auth();
+        let response = request_over_tls(server.addr, client_config).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("hello"), "{}", response);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn should_require_client_certificate() {
+        let dir = tempdir().unwrap();
+        let config = self_signed_config(dir.path());
+        // クライアント証明書を発行するCA
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "test ca");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let mut client_params = CertificateParams::new(vec![]).unwrap();
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, "automation");
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+        let server_config =
+            load_server_config(&config, Ipv4Addr::LOCALHOST, Some(&ca_path)).unwrap();
+        let server = TestServer::start(server_config).await;
+
+        // 証明書のCommon NameがExtensionとしてハンドラに渡される
+        let client_config = client_builder(&config.cert_path)
+            .with_client_auth_cert(
+                vec![CertificateDer::from(client_cert.der().to_vec())],
+                PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+        let response = request_over_tls(server.addr, client_config).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("automation"), "{}", response);
+
+        // 証明書を提示しないクライアントは拒否される
+        let client_config = client_builder(&config.cert_path).with_no_client_auth();
+        let result = request_over_tls(server.addr, client_config).await;
+        assert!(result.is_err(), "{:?}", result);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn should_serve_over_unix_socket() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+        let config = UnixSocketConfig {
+            path: path.clone(),
+            mode: "0660".to_string(),
+            owner: None,
+            group: None,
+        };
+        // 使われていないソケットファイルは作り直す
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = bind_unix(&config).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        // 使用中のソケットファイルは削除しない
+        assert!(bind_unix(&config).is_err());
+
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let handle = tokio::spawn(serve_unix(listener, app, async {
+            let _ = stopped.await;
+        }));
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_not_replace_regular_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("api.sock");
+        std::fs::write(&path, b"").unwrap();
+        let config = UnixSocketConfig {
+            path: path.clone(),
+            mode: "0600".to_string(),
+            owner: None,
+            group: None,
+        };
+        assert!(bind_unix(&config).is_err());
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Write},
+    net::Ipv4Addr,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::{
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tracing::{info, warn};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::auth::ClientCertificate;
+use crate::config::TlsConfig;
+
+/// 設定ファイルに従ってrustlsのサーバ設定を作成する
+///
+/// `generate_self_signed` が有効で証明書と秘密鍵がどちらも存在しない場合は自己署名証明書を生成する。
+/// `client_ca` を指定した場合は、そのCAが発行したクライアント証明書を要求する
+pub fn load_server_config(
+    config: &TlsConfig,
+    listen_address: Ipv4Addr,
+    client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, anyhow::Error> {
+    if config.generate_self_signed && !config.cert_path.exists() && !config.key_path.exists() {
+        generate_self_signed(&config.cert_path, &config.key_path, listen_address)?;
+    }
+    let certs = read_certs(&config.cert_path)?;
+    let key = read_private_key(&config.key_path)?;
+    info!(
+        "TLS certificate loaded from {:?} (SHA-256 fingerprint {})",
+        config.cert_path,
+        fingerprint(&certs[0])
+    );
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots.add(cert)?;
+            }
+            info!("Client certificates issued by {:?} are required", path);
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .context("Certificate and private key do not match")?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse {:?}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate found in {:?}", path);
+    }
+    Ok(certs)
+}
+
+fn read_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, anyhow::Error> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse {:?}", path))?
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {:?}", path))
+}
+
+/// 検証済みのクライアント証明書からSubjectのCommon Nameを取り出す
+pub fn client_certificate(cert: &CertificateDer) -> ClientCertificate {
+    let common_name = X509Certificate::from_der(cert.as_ref())
+        .ok()
+        .and_then(|(_, cert)| {
+            cert.subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string)
+        });
+    ClientCertificate { common_name }
+}
+
+/// 証明書のSHA-256フィンガープリント (コロン区切りの16進数)
+fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// localhostと待ち受けアドレスをSANに持つ自己署名証明書を生成し、PEM形式で書き出す
+///
+/// 秘密鍵はパーミッション0600で作成する
+fn generate_self_signed(
+    cert_path: &Path,
+    key_path: &Path,
+    listen_address: Ipv4Addr,
+) -> Result<(), anyhow::Error> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if !listen_address.is_loopback() && !listen_address.is_unspecified() {
+        names.push(listen_address.to_string());
+    }
+    let certified = rcgen::generate_simple_self_signed(names)?;
+    for path in [cert_path, key_path] {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut key_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(key_path)
+        .with_context(|| format!("Failed to create {:?}", key_path))?;
+    key_file.write_all(certified.key_pair.serialize_pem().as_bytes())?;
+    key_file.sync_all()?;
+    let mut cert_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(cert_path)
+        .with_context(|| format!("Failed to create {:?}", cert_path))?;
+    cert_file.write_all(certified.cert.pem().as_bytes())?;
+    cert_file.sync_all()?;
+    warn!(
+        "Generated a self-signed certificate at {:?}. Clients must trust it explicitly (e.g. `curl --cacert`).",
+        cert_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::Ipv4Addr, os::unix::fs::PermissionsExt};
+
+    use tempfile::tempdir;
+
+    use super::load_server_config;
+    use crate::config::TlsConfig;
+
+    #[test]
+    fn generate_and_reload_self_signed() {
+        let dir = tempdir().unwrap();
+        let config = TlsConfig {
+            cert_path: dir.path().join("tls/cert.pem"),
+            key_path: dir.path().join("tls/key.pem"),
+            generate_self_signed: true,
+        };
+        let server_config =
+            load_server_config(&config, Ipv4Addr::new(192, 168, 0, 2), None).unwrap();
+        assert_eq!(server_config.alpn_protocols.len(), 2);
+        let mode = std::fs::metadata(&config.key_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 2回目以降は生成済みの証明書を読み込む
+        let cert = std::fs::read(&config.cert_path).unwrap();
+        load_server_config(&config, Ipv4Addr::new(192, 168, 0, 2), None).unwrap();
+        assert_eq!(std::fs::read(&config.cert_path).unwrap(), cert);
+    }
+
+    #[test]
+    fn missing_certificate_is_error() {
+        let dir = tempdir().unwrap();
+        let config = TlsConfig {
+            cert_path: dir.path().join("cert.pem"),
+            key_path: dir.path().join("key.pem"),
+            generate_self_signed: false,
+        };
+        assert!(load_server_config(&config, Ipv4Addr::LOCALHOST, None).is_err());
+    }
+}